@@ -1,10 +1,15 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use topaz_tak::board::find_placement_road;
 use topaz_tak::board::{Bitboard6, Board6};
-use topaz_tak::eval::{Evaluator, Evaluator6, LOSE_SCORE};
-use topaz_tak::search::root_minimax;
+use topaz_tak::eval::{Evaluator, Evaluator6, Weights6, LOSE_SCORE};
+use topaz_tak::search::{root_minimax, search, SearchInfo};
+use topaz_tak::transposition_table::{HashEntry, HashTable, ScoreCutoff};
+use std::sync::Arc;
+use std::time::Instant;
 use topaz_tak::{execute_moves_check_valid, generate_all_moves, perft, Color, GameMove, TakBoard};
 
+const TT_LAYOUT: &str = if cfg!(feature = "legacy-tt-layout") { "legacy" } else { "compact" };
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     // c.bench_function("small perft", |b| {
     //     b.iter(|| execute_small_perft(black_box(2)))
@@ -17,10 +22,35 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     // let pos = get_positions();
     // let eval = Evaluator6 {};
     c.bench_function("tak_threat", |b| {
-        b.iter(|| check_for_tak(black_box(&mut pos), black_box(&mut legal)))
+        b.iter(|| check_for_tak(black_box(&mut pos), black_box(&legal)))
+    });
+    let eval = Weights6::default();
+    c.bench_function("evaluate", |b| {
+        b.iter(|| evaluate_positions(black_box(&pos), black_box(&eval)))
     });
+    let table = HashTable::with_mb(64);
+    c.bench_function("tt_put_get", |b| b.iter(|| tt_put_get(black_box(&table))));
+    // Node rate is the node count printed here divided by the measured time. To
+    // compare table entry layouts, run once with --features legacy-tt-layout and
+    // once without, criterion then reports the change against the previous run
+    let table = Arc::new(HashTable::with_mb(16));
+    let start = Instant::now();
+    let nodes = search_positions(&mut pos, &eval, &table, 5);
+    println!(
+        "search_depth_5 with the {} tt layout: {} nodes, {:.0} nodes/s",
+        TT_LAYOUT,
+        nodes,
+        nodes as f64 / start.elapsed().as_secs_f64()
+    );
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    group.bench_function("search_depth_5", |b| {
+        b.iter(|| search_positions(black_box(&mut pos), &eval, &table, 5))
+    });
+    group.finish();
 }
 
+#[allow(dead_code)]
 fn execute_small_perft(depth: usize) {
     let ptn_moves = &[
         "c2", "c3", "d3", "b3", "c4", "1c2+", "1d3<", "1b3>", "1c4-", "Cc2", "a1", "1c2+", "a2",
@@ -37,8 +67,8 @@ fn execute_small_perft(depth: usize) {
 }
 
 fn check_for_tak(boards: &mut [Board6], legal_moves: &[Vec<GameMove>]) {
-    for (board, moves) in boards.into_iter().zip(legal_moves.iter()) {
-        board.get_tak_threats(&moves, None);
+    for (board, moves) in boards.iter_mut().zip(legal_moves.iter()) {
+        board.get_tak_threats(moves, None);
     }
 }
 
@@ -59,10 +89,46 @@ fn evaluate_positions<E: Evaluator<Game = Board6>>(positions: &[Board6], eval: &
     for pos in positions.iter() {
         sum += eval.evaluate(pos, 1);
     }
-    return sum;
+    sum
+}
+
+fn tt_put_get(table: &HashTable) -> usize {
+    let mut found = 0;
+    let mut hash: u64 = 0x9E37_79B9_7F4A_7C15;
+    for i in 0..10_000 {
+        // Xorshift to spread the probes over the whole table
+        hash ^= hash << 13;
+        hash ^= hash >> 7;
+        hash ^= hash << 17;
+        let entry = HashEntry::new(hash, GameMove::null_move(), ScoreCutoff::Exact(i), 4, 10);
+        table.put(hash, entry);
+        if table.get(&hash).is_some() {
+            found += 1;
+        }
+    }
+    found
+}
+
+fn search_positions<E: Evaluator<Game = Board6>>(
+    positions: &mut [Board6],
+    eval: &E,
+    table: &Arc<HashTable>,
+    depth: usize,
+) -> usize {
+    let mut nodes = 0;
+    for pos in positions.iter_mut() {
+        table.clear();
+        let mut info = SearchInfo::new(depth, 0)
+            .shared_table(table.clone())
+            .quiet(true);
+        search(pos, eval, &mut info);
+        nodes += info.nodes;
+    }
+    nodes
 }
 
-fn small_minimax(depth: u16) {
+#[allow(dead_code)]
+fn small_minimax() {
     let tps = "2,1,1,1,1,2S/1,12,1,x,1C,11112/x,2,2,212,2C,11121/2,21122,x2,1,x/x3,1,1,x/x2,2,21,x,112S 1 34";
     let mut board = Board6::try_from_tps(tps).unwrap();
     let eval = Evaluator6 {};
@@ -72,6 +138,7 @@ fn small_minimax(depth: u16) {
     assert_eq!(mv, only_move);
 }
 
+#[allow(dead_code)]
 fn placement_road(_x: ()) {
     // "2,x,2,2,2C,1/1,2212,1,1,x,121/12,222,1S,2S,2,x/2,x,2,x2,1/1,221,22,121,121,11C/x,2,2,2,x,1 2 32";
     let rp = Bitboard6::new(18144415765381120);
@@ -6,9 +6,11 @@ use board_game_traits::{Color, GameResult, Position};
 use std::fmt;
 
 mod bitboard;
+mod features;
 mod piece;
 mod stack;
 mod zobrist;
+pub use features::*;
 pub use piece::*;
 pub use stack::*;
 
@@ -24,7 +26,7 @@ pub trait TakBoard: Position<Move = GameMove, ReverseMove = RevGameMove> {
     fn rev_null_move(&mut self);
     fn get_tak_threats(
         &mut self,
-        legal_moves: &Vec<GameMove>,
+        legal_moves: &[GameMove],
         hint: Option<&[GameMove]>,
     ) -> Vec<GameMove>;
     fn can_make_road(
@@ -53,7 +55,12 @@ pub trait TakBoard: Position<Move = GameMove, ReverseMove = RevGameMove> {
     fn make_ptn_moves(&mut self, moves: &[&str]) -> Option<()>;
     fn bits(&self) -> &BitboardStorage<Self::Bits>;
     fn board(&self) -> &[Stack];
+    fn stack_features(&self) -> &StackFeatures;
     fn with_komi(self, half_flats: u8) -> Self;
+    fn try_from_tps(tps: &str) -> Result<Self>
+    where
+        Self: Sized;
+    fn to_tps(&self) -> String;
     fn komi(&self) -> u8;
     fn flat_diff(&self, player: Color) -> i32;
 }
@@ -76,9 +83,21 @@ macro_rules! board_impl {
                     flats_left: [Self::FLATS, Self::FLATS],
                     caps_left: [Self::CAPS, Self::CAPS],
                     bits,
+                    features: StackFeatures::new(),
                     komi: 0,
                 }
             }
+            /// Refreshes the stack features around every square touched by the move
+            fn refresh_features(&mut self, m: GameMove) {
+                let mut touched = <$bits>::index_to_bit(m.src_index());
+                if m.is_stack_move() {
+                    for qstep in m.quantity_iter(Self::SIZE) {
+                        touched |= <$bits>::index_to_bit(qstep.index);
+                    }
+                }
+                self.features
+                    .refresh(&self.board, &self.bits, touched | touched.adjacent());
+            }
             fn flat_winner(&self) -> GameResult {
                 let white_score = 2 * self.bits.flat_score(Color::White);
                 let black_score = 2 * self.bits.flat_score(Color::Black) + self.komi as u32;
@@ -122,12 +141,15 @@ macro_rules! board_impl {
                 }
                 for stack in board.board.iter() {
                     for piece in stack.iter() {
-                        match piece {
-                            Piece::WhiteCap => board.caps_left[0] -= 1,
-                            Piece::BlackCap => board.caps_left[1] -= 1,
-                            Piece::WhiteFlat | Piece::WhiteWall => board.flats_left[0] -= 1,
-                            Piece::BlackFlat | Piece::BlackWall => board.flats_left[1] -= 1,
-                        }
+                        let reserve = match piece {
+                            Piece::WhiteCap => &mut board.caps_left[0],
+                            Piece::BlackCap => &mut board.caps_left[1],
+                            Piece::WhiteFlat | Piece::WhiteWall => &mut board.flats_left[0],
+                            Piece::BlackFlat | Piece::BlackWall => &mut board.flats_left[1],
+                        };
+                        *reserve = reserve
+                            .checked_sub(1)
+                            .ok_or_else(|| anyhow!("Too many pieces for this board size"))?;
                     }
                 }
                 let active_player = match data[1] {
@@ -137,12 +159,19 @@ macro_rules! board_impl {
                 };
                 board.active_player = active_player;
                 board.move_num = data[2].parse()?;
+                board.features = StackFeatures::build(&board.board, &board.bits);
                 let zobrist_hash = zobrist::TABLE.manual_build_hash(&board);
                 board.bits.set_zobrist(zobrist_hash);
                 Ok(board)
             }
         }
 
+        impl Default for $t {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
         impl TakBoard for $t {
             type Bits = $bits;
             const SIZE: usize = $sz;
@@ -185,7 +214,7 @@ macro_rules! board_impl {
             }
             fn get_tak_threats(
                 &mut self,
-                legal_moves: &Vec<GameMove>,
+                legal_moves: &[GameMove],
                 hint: Option<&[GameMove]>,
             ) -> Vec<GameMove> {
                 let mut tak_threats = Vec::new();
@@ -339,6 +368,10 @@ macro_rules! board_impl {
                 &self.board
             }
 
+            fn stack_features(&self) -> &StackFeatures {
+                &self.features
+            }
+
             fn with_komi(mut self, half_flats: u8) -> Self {
                 self.komi = half_flats;
                 self
@@ -348,6 +381,39 @@ macro_rules! board_impl {
                 self.komi
             }
 
+            fn try_from_tps(tps: &str) -> Result<Self> {
+                // Resolves to the inherent constructor
+                Self::try_from_tps(tps)
+            }
+
+            fn to_tps(&self) -> String {
+                let mut rows = Vec::with_capacity(Self::SIZE);
+                for row in self.board.chunks(Self::SIZE) {
+                    let mut tiles = Vec::new();
+                    let mut empty = 0;
+                    for stack in row.iter() {
+                        if stack.len() == 0 {
+                            empty += 1;
+                            continue;
+                        }
+                        if empty > 0 {
+                            tiles.push(tps_empty(empty));
+                            empty = 0;
+                        }
+                        tiles.push(tps_stack(stack));
+                    }
+                    if empty > 0 {
+                        tiles.push(tps_empty(empty));
+                    }
+                    rows.push(tiles.join(","));
+                }
+                let player = match self.active_player {
+                    Color::White => 1,
+                    Color::Black => 2,
+                };
+                format!("{} {} {}", rows.join("/"), player, self.move_num)
+            }
+
             fn flat_diff(&self, player: Color) -> i32 {
                 let white = self.bits.flat_score(Color::White) as i32;
                 let black = self.bits.flat_score(Color::Black) as i32;
@@ -422,6 +488,7 @@ macro_rules! board_impl {
                     let pieces_moved = rev_m.game_move.number() as usize;
                     origin.reverse_top(pieces_moved, &mut self.bits);
                 }
+                self.refresh_features(m);
             }
             fn do_move(&mut self, m: GameMove) -> <Self as Position>::ReverseMove {
                 let swap_pieces = self.move_num == 1;
@@ -441,6 +508,7 @@ macro_rules! board_impl {
                     } else {
                         self.flats_left[piece.owner() as usize] -= 1;
                     }
+                    self.refresh_features(m);
                     RevGameMove::new(m, src_index)
                 } else {
                     let num_pieces = m.number() as usize;
@@ -456,13 +524,13 @@ macro_rules! board_impl {
                         last_idx = sq;
                     }
                     let last_square = &mut self.board[last_idx];
-                    let len = last_square.len();
-                    if len >= 2 {
-                        if last_square.try_crush_wall::<<Self as TakBoard>::Bits>() {
-                            return RevGameMove::new(m.set_crush(), last_idx);
-                        }
+                    let crush = last_square.try_crush_wall::<<Self as TakBoard>::Bits>();
+                    self.refresh_features(m);
+                    if crush {
+                        RevGameMove::new(m.set_crush(), last_idx)
+                    } else {
+                        RevGameMove::new(m, last_idx)
                     }
-                    RevGameMove::new(m, last_idx)
                 }
             }
         }
@@ -504,6 +572,30 @@ macro_rules! board_impl {
     };
 }
 
+#[derive(PartialEq, Clone)]
+pub struct Board3 {
+    pub board: [Stack; Self::SIZE * Self::SIZE],
+    active_player: Color,
+    move_num: usize,
+    flats_left: [usize; 2],
+    caps_left: [usize; 2],
+    pub bits: BitboardStorage<<Self as TakBoard>::Bits>,
+    features: StackFeatures,
+    komi: u8,
+}
+
+#[derive(PartialEq, Clone)]
+pub struct Board4 {
+    pub board: [Stack; Self::SIZE * Self::SIZE],
+    active_player: Color,
+    move_num: usize,
+    flats_left: [usize; 2],
+    caps_left: [usize; 2],
+    pub bits: BitboardStorage<<Self as TakBoard>::Bits>,
+    features: StackFeatures,
+    komi: u8,
+}
+
 #[derive(PartialEq, Clone)]
 pub struct Board5 {
     pub board: [Stack; Self::SIZE * Self::SIZE],
@@ -512,6 +604,7 @@ pub struct Board5 {
     flats_left: [usize; 2],
     caps_left: [usize; 2],
     pub bits: BitboardStorage<<Self as TakBoard>::Bits>,
+    features: StackFeatures,
     komi: u8,
 }
 
@@ -523,6 +616,7 @@ pub struct Board6 {
     flats_left: [usize; 2],
     caps_left: [usize; 2],
     pub bits: BitboardStorage<<Self as TakBoard>::Bits>,
+    features: StackFeatures,
     komi: u8,
 }
 
@@ -534,12 +628,28 @@ pub struct Board7 {
     flats_left: [usize; 2],
     caps_left: [usize; 2],
     pub bits: BitboardStorage<<Self as TakBoard>::Bits>,
+    features: StackFeatures,
     komi: u8,
 }
 
+#[derive(PartialEq, Clone)]
+pub struct Board8 {
+    pub board: [Stack; Self::SIZE * Self::SIZE],
+    active_player: Color,
+    move_num: usize,
+    flats_left: [usize; 2],
+    caps_left: [usize; 2],
+    pub bits: BitboardStorage<<Self as TakBoard>::Bits>,
+    features: StackFeatures,
+    komi: u8,
+}
+
+board_impl![Board3, Bitboard3, 3, 10, 0];
+board_impl![Board4, Bitboard4, 4, 15, 0];
 board_impl![Board5, Bitboard5, 5, 21, 1];
 board_impl![Board6, Bitboard6, 6, 30, 1];
 board_impl![Board7, Bitboard7, 7, 40, 2];
+board_impl![Board8, Bitboard8, 8, 50, 2];
 
 pub fn find_placement_road<T>(player: Color, road_pieces: T, empty: T) -> Option<GameMove>
 where
@@ -554,6 +664,30 @@ where
     }
 }
 
+fn tps_empty(count: usize) -> String {
+    if count == 1 {
+        "x".to_string()
+    } else {
+        format!("x{}", count)
+    }
+}
+
+fn tps_stack(stack: &Stack) -> String {
+    let mut s = String::new();
+    for piece in stack.iter() {
+        match piece.owner() {
+            Color::White => s.push('1'),
+            Color::Black => s.push('2'),
+        }
+    }
+    match stack.last() {
+        Some(Piece::WhiteWall) | Some(Piece::BlackWall) => s.push('S'),
+        Some(Piece::WhiteCap) | Some(Piece::BlackCap) => s.push('C'),
+        _ => {}
+    }
+    s
+}
+
 fn parse_tps_stack(tile: &str) -> Result<Vec<Piece>> {
     let mut vec = Vec::new();
     for c in tile.chars() {
@@ -594,6 +728,69 @@ impl Board6 {
 mod test {
     use super::*;
     #[test]
+    pub fn tps_round_trip() {
+        let tps = "x6/x2,2,x3/x3,2C,x2/x2,211S,x2,2/x6/x,1,1,2,2,1 2 7";
+        let board = Board6::try_from_tps(tps).unwrap();
+        assert_eq!(board.to_tps(), tps);
+        assert_eq!(Board6::new().to_tps(), "x6/x6/x6/x6/x6/x6 1 1");
+    }
+    #[test]
+    pub fn stack_counts() {
+        fn check(board: &Board6) {
+            for stack in board.board.iter() {
+                let mut expected = (0, 0);
+                if let Some(top) = stack.last() {
+                    for piece in stack.iter().rev().skip(1) {
+                        if piece.owner() == top.owner() {
+                            expected.1 += 1;
+                        } else {
+                            expected.0 += 1;
+                        }
+                    }
+                }
+                assert_eq!(stack.captive_friendly(), expected);
+            }
+        }
+        let tps = "2,x4,1/x4,1,x/x,2,12C,1,1,x/x,1,2,21C,x2/x,2,2,x3/x2,2,1,x2 1 10";
+        let mut board = Board6::try_from_tps(tps).unwrap();
+        check(&board);
+        let mut moves = Vec::new();
+        generate_all_moves(&board, &mut moves);
+        for m in moves {
+            let rev = board.do_move(m);
+            check(&board);
+            board.reverse_move(rev);
+            check(&board);
+        }
+    }
+    #[test]
+    pub fn stack_features() {
+        fn check(board: &Board6) {
+            let expected = StackFeatures::build(&board.board, &board.bits);
+            assert!(board.features == expected, "{}", board.to_tps());
+        }
+        let tps = "2,2,2,21,12,x/x4,2,x/x4,2C,x/1,2,12,122211C,x2/x2,1S,1,12,1/x3,2S,1,1 1 19";
+        let mut board = Board6::try_from_tps(tps).unwrap();
+        check(&board);
+        let mut moves = Vec::new();
+        let mut replies = Vec::new();
+        generate_all_moves(&board, &mut moves);
+        for m in moves {
+            let rev = board.do_move(m);
+            check(&board);
+            replies.clear();
+            generate_all_moves(&board, &mut replies);
+            for &reply in replies.iter() {
+                let rev_reply = board.do_move(reply);
+                check(&board);
+                board.reverse_move(rev_reply);
+            }
+            board.reverse_move(rev);
+            check(&board);
+        }
+        assert!(board == Board6::try_from_tps(tps).unwrap());
+    }
+    #[test]
     pub fn test_read_tps() {
         let example_tps = "x6/x2,2,x3/x3,2C,x2/x2,211S,x2,2/x6/x,1,1,2,2,1 2 7";
         let board = Board6::try_from_tps(example_tps);
@@ -607,7 +804,7 @@ mod test {
         let stack = vec![Piece::BlackFlat, Piece::WhiteFlat, Piece::WhiteWall];
         // b.tile_mut(3, 2) = &mut stack;
         let stack_dest = b.tile_mut(3, 2);
-        stack_dest.extend(stack.into_iter(), &mut board.bits);
+        stack_dest.extend(stack, &mut board.bits);
         b.tile_mut(3, 5).push(Piece::BlackFlat, &mut board.bits);
 
         b.tile_mut(5, 1).push(Piece::WhiteFlat, &mut board.bits);
@@ -695,6 +892,61 @@ mod test {
         assert_eq!(&p_res[..], &[1, 190, 20698]);
     }
 
+    #[test]
+    pub fn board8_perft() {
+        let mut board = Board8::new();
+        let p_res: Vec<_> = (0..3)
+            .map(|depth| crate::perft(&mut board, depth as u16))
+            .collect();
+        assert_eq!(&p_res[..], &[1, 64, 4032]);
+        let tps = "x8/x8/x8/x2,1S,21C,x4/x3,2,x4/x8/x8/x7,2 1 6";
+        let mut board = Board8::try_from_tps(tps).unwrap();
+        assert_eq!(board.caps_reserve(Color::White), 1);
+        let res = crate::execute_moves_check_valid(&mut board, &["d5<*"]);
+        assert!(res.is_ok());
+        assert_eq!(
+            format!("{:?}", board),
+            "x,x,x,x,x,x,x,x/x,x,x,x,x,x,x,x/x,x,x,x,x,x,x,x/x,x,11C,2,x,x,x,x/x,x,x,2,x,x,x,x/x,x,x,x,x,x,x,x/x,x,x,x,x,x,x,x/x,x,x,x,x,x,x,2 2 6"
+        );
+    }
+
+    #[test]
+    pub fn small_board_perft() {
+        let mut board = Board3::new();
+        let p_res: Vec<_> = (0..5)
+            .map(|depth| crate::perft(&mut board, depth as u16))
+            .collect();
+        assert_eq!(&p_res[..], &[1, 9, 72, 1200, 17792]);
+        let mut board = Board4::new();
+        let p_res: Vec<_> = (0..4)
+            .map(|depth| crate::perft(&mut board, depth as u16))
+            .collect();
+        assert_eq!(&p_res[..], &[1, 16, 240, 7440]);
+        // Neither size has capstones
+        assert!(Board3::try_from_tps("1C,x2/x3/x2,2 1 2").is_err());
+        assert!(Board4::try_from_tps("x4/x4/x4/x3,1C 2 1").is_err());
+    }
+
+    #[test]
+    pub fn small_board_reserves() {
+        let tps = "2,1,2/2,1,x/1,2,1 1 5";
+        let mut board = Board3::try_from_tps(tps).unwrap();
+        assert_eq!(board.pieces_reserve(Color::White), 6);
+        assert_eq!(board.pieces_reserve(Color::Black), 6);
+        let res = crate::execute_moves_check_valid(&mut board, &["c2"]);
+        assert!(res.is_ok());
+        assert_eq!(board.flat_game(), Some(GameResult::WhiteWin));
+        // White runs out of pieces before the board is full
+        let tps = "2,1,2/1,2,1/x,21212121211,x 1 12";
+        let mut board = Board3::try_from_tps(tps).unwrap();
+        assert_eq!(board.pieces_reserve(Color::White), 1);
+        assert_eq!(board.flat_game(), None);
+        let res = crate::execute_moves_check_valid(&mut board, &["a1"]);
+        assert!(res.is_ok());
+        assert_eq!(board.pieces_reserve(Color::White), 0);
+        assert_eq!(board.flat_game(), Some(GameResult::WhiteWin));
+    }
+
     #[test]
     pub fn board_fill() {
         let tps = "1,2,1,1,1,x/2,2,2,1,21,1/2,2,112C,21S,2,2/2,1,221C,1,12,212/1,1,1,2,1,2/2,2,1,1,2,112S 2 30";
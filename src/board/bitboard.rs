@@ -57,18 +57,16 @@ where
         !(self.white | self.black)
     }
     pub fn road_pieces(&self, color: Color) -> T {
-        let road_pieces = match color {
+        match color {
             Color::White => (self.flat | self.cap) & self.white,
             Color::Black => (self.flat | self.cap) & self.black,
-        };
-        road_pieces
+        }
     }
     pub fn blocker_pieces(&self, color: Color) -> T {
-        let blocker_pieces = match color {
+        match color {
             Color::White => (self.wall | self.cap) & self.white,
             Color::Black => (self.wall | self.cap) & self.black,
-        };
-        blocker_pieces
+        }
     }
     pub fn all_pieces(&self, color: Color) -> T {
         match color {
@@ -189,6 +187,89 @@ pub trait Bitboard:
     fn size() -> usize;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bitboard3(u64);
+
+impl Bitboard3 {
+    const BIT_TO_INDEX: [usize; 64] = Self::build_bit_to_index_table();
+    const INDEX_TO_BIT: [u64; 9] = Self::build_index_to_bit_table();
+    const TOP: Bitboard3 = Bitboard3::new(0xe00);
+    const BOTTOM: Bitboard3 = Bitboard3::new(0xe000000);
+    const LEFT: Bitboard3 = Bitboard3::new(0x2020200);
+    const RIGHT: Bitboard3 = Bitboard3::new(0x8080800);
+    const LEFT_TOP: Bitboard3 = Bitboard3::new(Self::LEFT.0 | Self::TOP.0);
+    const INNER: u64 = 0xe0e0e00; // 3x3 Board
+    pub const fn new(data: u64) -> Self {
+        Self(data & Self::INNER)
+    }
+    #[rustfmt::skip]
+    const fn build_bit_to_index_table() -> [usize; 64] {
+        const EMPTY: usize = 100;
+        let arr: [usize; 64] = [
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY,  0,  1,  2, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY,  3,  4,  5, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY,  6,  7,  8, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY
+        ];
+        arr
+    }
+    #[rustfmt::skip]
+    const fn build_index_to_bit_table() -> [u64; 9] {
+        let arr: [u64; 9] = [
+             9,	10,	11,
+            17,	18,	19,
+            25,	26,	27,
+        ];
+        arr
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bitboard4(u64);
+
+impl Bitboard4 {
+    const BIT_TO_INDEX: [usize; 64] = Self::build_bit_to_index_table();
+    const INDEX_TO_BIT: [u64; 16] = Self::build_index_to_bit_table();
+    const TOP: Bitboard4 = Bitboard4::new(0x1e00);
+    const BOTTOM: Bitboard4 = Bitboard4::new(0x1e00000000);
+    const LEFT: Bitboard4 = Bitboard4::new(0x202020200);
+    const RIGHT: Bitboard4 = Bitboard4::new(0x1010101000);
+    const LEFT_TOP: Bitboard4 = Bitboard4::new(Self::LEFT.0 | Self::TOP.0);
+    const INNER: u64 = 0x1e1e1e1e00; // 4x4 Board
+    pub const fn new(data: u64) -> Self {
+        Self(data & Self::INNER)
+    }
+    #[rustfmt::skip]
+    const fn build_bit_to_index_table() -> [usize; 64] {
+        const EMPTY: usize = 100;
+        let arr: [usize; 64] = [
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY,  0,  1,  2,  3, EMPTY, EMPTY, EMPTY,
+            EMPTY,  4,  5,  6,  7, EMPTY, EMPTY, EMPTY,
+            EMPTY,  8,  9, 10, 11, EMPTY, EMPTY, EMPTY,
+            EMPTY, 12, 13, 14, 15, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+            EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY
+        ];
+        arr
+    }
+    #[rustfmt::skip]
+    const fn build_index_to_bit_table() -> [u64; 16] {
+        let arr: [u64; 16] = [
+             9,	10,	11,	12,
+            17,	18,	19,	20,
+            25,	26,	27,	28,
+            33,	34,	35,	36,
+        ];
+        arr
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Bitboard5(u64);
 
@@ -210,8 +291,8 @@ impl Bitboard5 {
         let arr: [usize; 64] = [
             EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
             EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
-            EMPTY, 00, 01, 02, 03, 04, EMPTY, EMPTY,
-            EMPTY, 05, 06, 07, 08, 09, EMPTY, EMPTY,
+            EMPTY,  0,  1,  2,  3,  4, EMPTY, EMPTY,
+            EMPTY,  5,  6,  7,  8,  9, EMPTY, EMPTY,
             EMPTY, 10, 11, 12, 13, 14, EMPTY, EMPTY,
             EMPTY, 15, 16, 17, 18, 19, EMPTY, EMPTY,
             EMPTY, 20, 21, 22, 23, 24, EMPTY, EMPTY,
@@ -252,8 +333,8 @@ impl Bitboard6 {
         const EMPTY: usize = 100;
         let arr: [usize; 64] = [
             EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
-            EMPTY, 00, 01, 02, 03, 04, 05, EMPTY,
-            EMPTY, 06, 07, 08, 09, 10, 11, EMPTY,
+            EMPTY,  0,  1,  2,  3,  4,  5, EMPTY,
+            EMPTY,  6,  7,  8,  9, 10, 11, EMPTY,
             EMPTY, 12, 13, 14, 15, 16, 17, EMPTY,
             EMPTY, 18, 19, 20, 21, 22, 23, EMPTY,
             EMPTY, 24, 25, 26, 27, 28, 29, EMPTY,
@@ -265,7 +346,7 @@ impl Bitboard6 {
     #[rustfmt::skip]
     const fn build_index_to_bit_table() -> [u64; 36] {
         let arr: [u64; 36] = [
-            09,	10,	11,	12,	13,	14,
+             9,	10,	11,	12,	13,	14,
             17,	18,	19,	20,	21,	22,
             25,	26,	27,	28,	29,	30,
             33,	34,	35,	36,	37,	38,
@@ -296,8 +377,8 @@ impl Bitboard7 {
         const EMPTY: usize = 100;
         let arr: [usize; 64] = [
             EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
-            00, 01, 02, 03, 04, 05, 06, EMPTY,
-            07, 08, 09, 10, 11, 12, 13, EMPTY,
+             0,  1,  2,  3,  4,  5,  6, EMPTY,
+             7,  8,  9, 10, 11, 12, 13, EMPTY,
             14, 15, 16, 17, 18, 19, 20, EMPTY,
             21, 22, 23, 24, 25, 26, 27, EMPTY,
             28, 29, 30, 31, 32, 33, 34, EMPTY,
@@ -309,7 +390,7 @@ impl Bitboard7 {
     #[rustfmt::skip]
     const fn build_index_to_bit_table() -> [u64; 49] {
         let arr: [u64; 49] = [
-            08, 09,	10,	11,	12,	13,	14,
+             8,  9,	10,	11,	12,	13,	14,
             16, 17,	18,	19,	20,	21,	22,
             24, 25,	26,	27,	28,	29,	30,
             32, 33,	34,	35,	36,	37,	38,
@@ -321,8 +402,51 @@ impl Bitboard7 {
     }
 }
 
+/// A bitboard for the 8x8 board. All 64 bits are playable squares, so unlike the smaller
+/// bitboards there is no padding and the square index is equal to the bit index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bitboard8(u64);
+
+impl Bitboard8 {
+    const BIT_TO_INDEX: [usize; 64] = Self::build_bit_to_index_table();
+    const INDEX_TO_BIT: [u64; 64] = Self::build_index_to_bit_table();
+    const TOP: Bitboard8 = Bitboard8::new(0xff);
+    const BOTTOM: Bitboard8 = Bitboard8::new(0xff00000000000000);
+    const LEFT: Bitboard8 = Bitboard8::new(0x101010101010101);
+    const RIGHT: Bitboard8 = Bitboard8::new(0x8080808080808080);
+    const LEFT_TOP: Bitboard8 = Bitboard8::new(Self::LEFT.0 | Self::TOP.0);
+    const INNER: u64 = 0xffffffffffffffff; // 8x8 Board
+    pub const fn new(data: u64) -> Self {
+        Self(data & Self::INNER)
+    }
+    const fn build_bit_to_index_table() -> [usize; 64] {
+        let mut arr = [0; 64];
+        let mut idx = 0;
+        while idx < 64 {
+            arr[idx] = idx;
+            idx += 1;
+        }
+        arr
+    }
+    const fn build_index_to_bit_table() -> [u64; 64] {
+        let mut arr = [0; 64];
+        let mut idx = 0;
+        while idx < 64 {
+            arr[idx] = idx as u64;
+            idx += 1;
+        }
+        arr
+    }
+}
+
+/// Bitboard3 through Bitboard7 pad every row with at least one unused column, so a
+/// horizontal shift can never wrap onto the next row. Bitboard8 has no padding, so it
+/// provides masks that clear the squares which would otherwise wrap around.
 macro_rules! bitboard_impl {
     ($t: ty, $sz: expr) => {
+        bitboard_impl![$t, $sz, u64::MAX, u64::MAX];
+    };
+    ($t: ty, $sz: expr, $east_mask: expr, $west_mask: expr) => {
         impl Default for $t {
             fn default() -> Self {
                 Self(0)
@@ -391,8 +515,8 @@ macro_rules! bitboard_impl {
                 let data = self.0;
                 let up = data >> 8;
                 let down = data << 8;
-                let left = data >> 1;
-                let right = data << 1;
+                let left = (data >> 1) & $west_mask;
+                let right = (data << 1) & $east_mask;
                 Self::new(up | down | left | right)
             }
             fn critical_squares(self) -> Self {
@@ -431,7 +555,7 @@ macro_rules! bitboard_impl {
                     if (component & Self::LEFT).nonzero() && (component & Self::RIGHT).nonzero() {
                         return true;
                     }
-                    unchecked = unchecked & !component;
+                    unchecked &= !component;
                 }
                 false
             }
@@ -479,13 +603,13 @@ macro_rules! bitboard_impl {
                 Self::new(self.0 >> 8)
             }
             fn east(self) -> Self {
-                Self::new(self.0 << 1)
+                Self::new((self.0 << 1) & $east_mask)
             }
             fn south(self) -> Self {
                 Self::new(self.0 << 8)
             }
             fn west(self) -> Self {
-                Self::new(self.0 >> 1)
+                Self::new((self.0 >> 1) & $west_mask)
             }
             fn top() -> Self {
                 Self::TOP
@@ -503,9 +627,12 @@ macro_rules! bitboard_impl {
     };
 }
 
+bitboard_impl![Bitboard3, 3];
+bitboard_impl![Bitboard4, 4];
 bitboard_impl![Bitboard5, 5];
 bitboard_impl![Bitboard6, 6];
 bitboard_impl![Bitboard7, 7];
+bitboard_impl![Bitboard8, 8, !Bitboard8::LEFT.0, !Bitboard8::RIGHT.0];
 
 pub struct BitIndexIterator<T> {
     bits: T,
@@ -567,6 +694,25 @@ mod test {
         assert_eq!(stacks3, stacks4);
     }
     #[test]
+    pub fn bitboard8_edges() {
+        // h8 and a7 are consecutive bits, but must not be adjacent
+        let h8 = Bitboard8::index_to_bit(7);
+        let a7 = Bitboard8::index_to_bit(8);
+        assert!((h8.adjacent() & a7) == Bitboard8::ZERO);
+        assert!((a7.adjacent() & h8) == Bitboard8::ZERO);
+        assert_eq!(h8.adjacent().pop_count(), 2);
+        assert_eq!(h8.east(), Bitboard8::ZERO);
+        assert_eq!(a7.west(), Bitboard8::ZERO);
+        assert_eq!(Bitboard8::new(0xff00000000).lowest_index(), 32);
+        // Rows and columns
+        assert!(Bitboard8::new(0xff000000).check_road());
+        assert!(Bitboard8::new(0x1010101010101010).check_road());
+        assert!(!Bitboard8::new(0x7f000000).check_road());
+        assert!(!Bitboard8::new(0xff0).check_road());
+        assert_eq!(Bitboard8::new(0x7f000000).critical_squares().lowest_index(), 31);
+        assert!(Bitboard8::new(u64::MAX).all_ones());
+    }
+    #[test]
     pub fn test_critical_squares() {
         const TOP: Bitboard6 = Bitboard6::new(0x7e00);
         const BOTTOM: Bitboard6 = Bitboard6::new(0x7e000000000000);
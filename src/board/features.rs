@@ -0,0 +1,169 @@
+use super::bitboard::{BitIndexIterator, Bitboard, BitboardStorage};
+use super::{Piece, Stack};
+use board_game_traits::Color;
+
+/// Largest number of squares on any supported board
+const MAX_SQUARES: usize = 64;
+
+/// Weight independent evaluation features of a stack of two or more pieces
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct StackFeature {
+    pub top: Piece,
+    pub captive: i32,
+    pub friendly: i32,
+    /// The top is a capstone sitting directly on a friendly flat
+    pub support: bool,
+    /// The stack has too few places to spread to
+    pub immobile: bool,
+    /// Adjacent enemy blockers outweigh the friendly protection
+    pub unsafe_stack: bool,
+}
+
+impl StackFeature {
+    /// Computes the features of the stack at idx from the stack and the tops of its
+    /// neighbors, returning None for squares holding fewer than two pieces
+    pub fn compute<B: Bitboard>(
+        board: &[Stack],
+        bits: &BitboardStorage<B>,
+        idx: usize,
+    ) -> Option<Self> {
+        let stack = &board[idx];
+        if stack.len() < 2 {
+            return None;
+        }
+        let top = *stack.last().unwrap();
+        let (captive, friendly) = stack.captive_friendly();
+        let mut support = false;
+        let mut mobility = 0;
+        let mut safety = 0;
+        match top {
+            Piece::WhiteFlat | Piece::BlackFlat => {}
+            Piece::WhiteWall | Piece::BlackWall => {
+                safety += 16;
+            }
+            Piece::WhiteCap | Piece::BlackCap => {
+                safety += 64;
+                mobility += 1;
+                support = stack.from_top(1) == Some(Piece::flat(top.owner()));
+            }
+        }
+        let neighbors = B::index_to_bit(idx).adjacent();
+        let own = bits.all_pieces(top.owner()) & neighbors;
+        let enemy = bits.all_pieces(!top.owner()) & neighbors;
+        let count = |b: B| b.pop_count() as i32;
+        // Empty squares and enemy flats can take any number of pieces
+        mobility += 2 * count(bits.empty() & neighbors) + 2 * count(enemy & bits.flat);
+        mobility += count(own & (bits.flat | bits.cap));
+        safety += count(own & bits.flat) + 4 * count(own & bits.wall) + 32 * count(own & bits.cap);
+        safety -= 4 * count(enemy & bits.wall) + 32 * count(enemy & bits.cap);
+        Some(Self {
+            top,
+            captive,
+            friendly,
+            support,
+            immobile: mobility < 2 && !top.is_blocker(),
+            unsafe_stack: safety < 0,
+        })
+    }
+    /// Friendly pieces lost to immobility, since immobile stacks only keep half of them
+    pub fn lost_friendly(&self) -> i32 {
+        if self.immobile {
+            self.friendly - self.friendly / 2
+        } else {
+            0
+        }
+    }
+}
+
+/// Sums of [`StackFeature`] over the stacks of a board, grouped by the top piece.
+///
+/// The board refreshes the squares a move touches and their neighbors after every
+/// move, so the evaluation does not need to look at the neighborhood of each stack.
+#[derive(PartialEq, Clone)]
+pub struct StackFeatures {
+    squares: [Option<StackFeature>; MAX_SQUARES],
+    captive: [i32; 6],
+    unsafe_captive: [i32; 6],
+    friendly: [i32; 6],
+    lost_friendly: [i32; 6],
+    support: [i32; 2],
+}
+
+impl StackFeatures {
+    pub fn new() -> Self {
+        Self {
+            squares: [None; MAX_SQUARES],
+            captive: [0; 6],
+            unsafe_captive: [0; 6],
+            friendly: [0; 6],
+            lost_friendly: [0; 6],
+            support: [0; 2],
+        }
+    }
+    /// Computes the features of every stack from scratch
+    pub fn build<B: Bitboard>(board: &[Stack], bits: &BitboardStorage<B>) -> Self {
+        let mut features = Self::new();
+        features.refresh(board, bits, !B::ZERO);
+        features
+    }
+    /// Recomputes the features of the given squares
+    pub fn refresh<B: Bitboard>(&mut self, board: &[Stack], bits: &BitboardStorage<B>, squares: B) {
+        for idx in BitIndexIterator::new(squares) {
+            if board[idx].len() < 2 && self.squares[idx].is_none() {
+                continue;
+            }
+            let new = StackFeature::compute(board, bits, idx);
+            let old = std::mem::replace(&mut self.squares[idx], new);
+            if old != new {
+                if let Some(old) = old {
+                    self.apply(&old, -1);
+                }
+                if let Some(new) = new {
+                    self.apply(&new, 1);
+                }
+            }
+        }
+    }
+    fn apply(&mut self, feature: &StackFeature, sign: i32) {
+        let top = feature.top as usize - 1;
+        self.captive[top] += sign * feature.captive;
+        if feature.unsafe_stack {
+            self.unsafe_captive[top] += sign * feature.captive;
+        }
+        self.friendly[top] += sign * feature.friendly;
+        self.lost_friendly[top] += sign * feature.lost_friendly();
+        if feature.support {
+            self.support[feature.top.owner() as usize] += sign;
+        }
+    }
+    /// The features of the stack at idx, if it holds two or more pieces
+    pub fn square(&self, idx: usize) -> Option<&StackFeature> {
+        self.squares[idx].as_ref()
+    }
+    /// Captives under all stacks topped by the given piece
+    pub fn captive(&self, top: Piece) -> i32 {
+        self.captive[top as usize - 1]
+    }
+    /// Captives under unsafe stacks topped by the given piece
+    pub fn unsafe_captive(&self, top: Piece) -> i32 {
+        self.unsafe_captive[top as usize - 1]
+    }
+    /// Friendly pieces under all stacks topped by the given piece
+    pub fn friendly(&self, top: Piece) -> i32 {
+        self.friendly[top as usize - 1]
+    }
+    /// Friendly pieces discounted from immobile stacks topped by the given piece
+    pub fn lost_friendly(&self, top: Piece) -> i32 {
+        self.lost_friendly[top as usize - 1]
+    }
+    /// Number of supported capstones of the given color
+    pub fn support(&self, color: Color) -> i32 {
+        self.support[color as usize]
+    }
+}
+
+impl Default for StackFeatures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -29,22 +29,13 @@ impl Piece {
         }
     }
     pub fn is_wall(self) -> bool {
-        match self {
-            Piece::WhiteWall | Piece::BlackWall => true,
-            _ => false,
-        }
+        matches!(self, Piece::WhiteWall | Piece::BlackWall)
     }
     pub fn is_cap(self) -> bool {
-        match self {
-            Piece::WhiteCap | Piece::BlackCap => true,
-            _ => false,
-        }
+        matches!(self, Piece::WhiteCap | Piece::BlackCap)
     }
     pub fn is_blocker(self) -> bool {
-        match self {
-            Piece::WhiteFlat | Piece::BlackFlat => false,
-            _ => true,
-        }
+        !matches!(self, Piece::WhiteFlat | Piece::BlackFlat)
     }
     pub fn wall(color: Color) -> Self {
         match color {
@@ -90,15 +81,9 @@ impl Piece {
     }
     pub fn road_piece(self, color: Color) -> bool {
         if let Color::White = color {
-            match self {
-                Piece::WhiteFlat | Piece::WhiteCap => true,
-                _ => false,
-            }
+            matches!(self, Piece::WhiteFlat | Piece::WhiteCap)
         } else {
-            match self {
-                Piece::BlackFlat | Piece::BlackCap => true,
-                _ => false,
-            }
+            matches!(self, Piece::BlackFlat | Piece::BlackCap)
         }
     }
 }
@@ -5,6 +5,15 @@ use super::Piece;
 pub struct Stack {
     data: Vec<Piece>,
     index: usize,
+    /// Number of white and black pieces in the stack, kept up to date as pieces
+    /// move so that the evaluation does not need to walk every stack
+    counts: [u8; 2],
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Stack {
@@ -14,6 +23,7 @@ impl Stack {
         Stack {
             data: VEC,
             index: IDX,
+            counts: [0; 2],
         }
     }
     pub fn init(&mut self, index: usize) {
@@ -30,6 +40,7 @@ impl Stack {
     pub fn push<T: Bitboard>(&mut self, item: Piece, bits: &mut BitboardStorage<T>) {
         self.hash_out_top(bits);
         bits.zobrist_middle(item, self.index, self.len());
+        self.counts[item.owner() as usize] += 1;
         self.data.push(item);
         self.hash_in_top(bits);
     }
@@ -41,16 +52,29 @@ impl Stack {
         let ret = self.data.pop();
         if let Some(piece) = ret {
             bits.zobrist_middle(piece, self.index, self.len());
+            self.counts[piece.owner() as usize] -= 1;
         }
         self.hash_in_top(bits);
         ret
     }
-    pub fn iter(&self) -> std::slice::Iter<Piece> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Piece> {
         self.data.iter()
     }
     pub fn len(&self) -> usize {
         self.data.len()
     }
+    /// Returns the number of pieces below the top which are controlled by the opponent
+    /// of the top piece and by the owner of the top piece, in that order
+    pub fn captive_friendly(&self) -> (i32, i32) {
+        match self.data.last() {
+            Some(top) => {
+                let owner = top.owner() as usize;
+                let friendly = self.counts[owner] as i32 - 1;
+                (self.counts[1 - owner] as i32, friendly)
+            }
+            None => (0, 0),
+        }
+    }
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
@@ -102,11 +126,14 @@ impl Stack {
         self.hash_out_many(top_n, bits);
         let split_idx = self.len() - top_n;
         let vec = self.data.split_off(split_idx);
+        for piece in vec.iter() {
+            self.counts[piece.owner() as usize] -= 1;
+        }
         self.hash_in_top(bits);
         vec
     }
     fn hash_out_many<T: Bitboard>(&self, top_n: usize, bits: &mut BitboardStorage<T>) {
-        if self.len() == 0 {
+        if self.is_empty() {
             return;
         }
         self.hash_out_top(bits);
@@ -117,7 +144,7 @@ impl Stack {
         // Todo zobrist operations
     }
     fn hash_in_many<T: Bitboard>(&self, top_n: usize, bits: &mut BitboardStorage<T>) {
-        if self.len() == 0 {
+        if self.is_empty() {
             return;
         }
         self.hash_in_top(bits);
@@ -1,4 +1,4 @@
-use crate::board::Board7;
+use crate::board::Board8;
 use crate::Piece;
 use crate::TakBoard;
 use board_game_traits::Color;
@@ -7,12 +7,12 @@ use rand_xoshiro::Xoshiro256PlusPlus;
 
 pub const TABLE: ZobristTable = ZobristTable::new();
 
-const LENGTH: usize = Board7::SIZE;
-const MAX_PIECES: usize = Board7::FLATS * 2 + 2;
+const LENGTH: usize = Board8::SIZE;
+const MAX_PIECES: usize = Board8::FLATS * 2 + Board8::CAPS * 2;
 const TOPS: usize = (LENGTH + 1) * (LENGTH * LENGTH + 1);
 const STACKS: usize = MAX_PIECES * (LENGTH * LENGTH);
 const ARR_SIZE: usize = TOPS + STACKS * 2 + 2;
-/// A Zobrist Table holding enough random values to support a size 7 board
+/// A Zobrist Table holding enough random values to support a size 8 board
 pub struct ZobristTable {
     white_to_move: u64,
     black_to_move: u64,
@@ -22,6 +22,8 @@ pub struct ZobristTable {
 impl ZobristTable {
     const fn new() -> Self {
         // const TABLE_DATA: [u64; ARR_SIZE] = [0; ARR_SIZE];
+        // Only read here, to build TABLE
+        #[allow(clippy::large_const_arrays)]
         const TABLE_DATA: [u64; ARR_SIZE] = include!("zobrist.table");
         let len = TABLE_DATA.len();
         Self {
@@ -70,8 +72,8 @@ impl ZobristTable {
         ];
         let mut table = [0; ARR_SIZE];
         let mut rng = Xoshiro256PlusPlus::from_seed(SEED);
-        for idx in 0..table.len() {
-            table[idx] = rng.next_u64();
+        for entry in table.iter_mut() {
+            *entry = rng.next_u64();
         }
         let f_name = "src/board/zobrist.table";
         let f = File::create(f_name).unwrap();
@@ -126,6 +128,21 @@ mod test {
             assert_eq!(board.bits.zobrist(), init_zobrist);
         }
     }
+    #[test]
+    pub fn test_update_zobrist8() {
+        let tps = "x8/x3,2,x4/x8/x2,1S,21C,x,12,x2/x3,2,x,2S,x2/x2,1,x5/x8/x7,2 1 8";
+        let mut board = Board8::try_from_tps(tps).unwrap();
+        let mut moves = Vec::new();
+        let init_zobrist = TABLE.manual_build_hash(&board);
+        generate_all_moves(&board, &mut moves);
+        assert!(moves.iter().find(|&m| m.crush()).is_some());
+        for m in moves {
+            let rev = board.do_move(m);
+            assert_eq!(board.bits.zobrist(), TABLE.manual_build_hash(&board));
+            board.reverse_move(rev);
+            assert_eq!(board.bits.zobrist(), init_zobrist);
+        }
+    }
     // #[test]
     // pub fn dummy() {
     //     ZobristTable::build_table();
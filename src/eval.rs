@@ -1,16 +1,34 @@
 use super::{Bitboard, Piece, Stack};
-use crate::board::BitIndexIterator;
-use crate::board::Board6;
+use crate::board::{BitIndexIterator, StackFeature, StackFeatures};
+use crate::board::{Board5, Board6, Board7, Board8};
 use crate::board::TakBoard;
+use crate::RevGameMove;
 use board_game_traits::{Color, Position};
 use rand_core::{RngCore, SeedableRng};
 use rand_xoshiro::Xoshiro256PlusPlus;
+use std::marker::PhantomData;
+
+mod nnue;
+mod trace;
+mod weights_file;
+use trace::{Score, TermSink};
+pub use nnue::{Network, NnueEvaluator};
+pub use trace::{EvalTerm, EvalTrace};
+pub use weights_file::{WeightsFile, WEIGHTS_VERSION};
 
 pub trait Evaluator {
     type Game: TakBoard;
     fn evaluate(&self, game: &Self::Game, depth: usize) -> i32;
+    /// Randomly perturbs the evaluation so that games are not all identical
+    fn add_noise(&mut self) {}
+    /// Called by the search right after it makes a move, so that evaluators
+    /// keeping incremental state can follow the position
+    fn do_move(&self, _game: &Self::Game, _rev_move: RevGameMove) {}
+    /// Called by the search right after it takes a move back
+    fn reverse_move(&self, _game: &Self::Game, _rev_move: RevGameMove) {}
 }
 
+#[derive(Clone)]
 pub struct Evaluator6 {}
 
 impl Evaluator6 {
@@ -48,7 +66,7 @@ impl Evaluator for Evaluator6 {
             } else if stack.len() > 1 {
                 let top = *stack.last().unwrap();
                 let pw = Self::piece_weight(top) + LOCATION_WEIGHT[idx];
-                let (captive, friendly) = captive_friendly(&stack, top);
+                let (captive, friendly) = stack.captive_friendly();
                 let (c_mul, f_mul) = Self::stack_top_multiplier(top);
                 let stack_score = captive * c_mul + friendly * f_mul + pw;
                 if let Color::White = top.owner() {
@@ -63,259 +81,33 @@ impl Evaluator for Evaluator6 {
         score += white_connectivity as i32 * Self::CONNECTIVITY;
         score -= black_connectivity as i32 * Self::CONNECTIVITY;
         if let Color::White = game.side_to_move() {
-            if depth % 2 == 0 {
+            if depth.is_multiple_of(2) {
                 score
             } else {
                 score - Self::TEMPO_OFFSET
             }
         } else {
-            if depth % 2 == 0 {
-                -1 * score
+            if depth.is_multiple_of(2) {
+                -score
             } else {
-                -1 * score + Self::TEMPO_OFFSET
+                -score + Self::TEMPO_OFFSET
             }
         }
     }
 }
 
 pub const WIN_SCORE: i32 = 10_000;
-pub const LOSE_SCORE: i32 = -1 * WIN_SCORE;
-
-impl Evaluator for Weights6 {
-    type Game = Board6;
-    #[inline(never)]
-    fn evaluate(&self, game: &Self::Game, depth: usize) -> i32 {
-        let mut score = 0;
-        // let white_res = game.pieces_reserve(Color::White) as i32;
-        // let black_res = game.pieces_reserve(Color::Black) as i32;
-        // let mut flat_diff = (game.bits.flat & game.bits.white).pop_count() as i32
-        //     - (game.bits.flat & game.bits.black).pop_count() as i32;
-        // flat_diff *= 2;
-        // flat_diff -= game.komi() as i32;
-        // let white_res_lead = black_res - white_res;
-        // let white_leading = white_res_lead >= 4 &&
-
-        for (idx, stack) in game.board.iter().enumerate() {
-            if stack.len() == 1 {
-                let top = *stack.last().unwrap();
-                let mut pw = self.piece_weight(top) + self.location[idx];
-                if top.is_cap() {
-                    pw += self.location[idx];
-                }
-                if let Color::White = top.owner() {
-                    score += pw;
-                } else {
-                    score -= pw;
-                }
-            } else if stack.len() > 1 {
-                let top = *stack.last().unwrap();
-                let mut pw = self.piece_weight(top) + self.location[idx];
-                let (mut captive, mut friendly) = captive_friendly(&stack, top);
-                let (c_mul, f_mul) = self.stack_top_multiplier(top);
-                let mut mobility = 0;
-                let mut safety = 0;
-                match top {
-                    Piece::WhiteFlat | Piece::BlackFlat => {}
-                    Piece::WhiteWall | Piece::BlackWall => {
-                        safety += 16;
-                    }
-                    Piece::WhiteCap => {
-                        safety += 64;
-                        mobility += 1;
-                        if let Some(Piece::WhiteFlat) = stack.from_top(1) {
-                            pw += 30;
-                        }
-                    }
-                    Piece::BlackCap => {
-                        safety += 64;
-                        mobility += 1;
-                        if let Some(Piece::BlackFlat) = stack.from_top(1) {
-                            pw += 30;
-                        }
-                    }
-                }
-                let neighbors = <Board6 as TakBoard>::Bits::index_to_bit(idx).adjacent();
-                for sq in BitIndexIterator::new(neighbors) {
-                    if let Some(piece) = game.board[sq].last() {
-                        if piece.owner() == top.owner() {
-                            match piece {
-                                Piece::WhiteFlat | Piece::BlackFlat => {
-                                    safety += 1;
-                                    mobility += 1;
-                                }
-                                Piece::WhiteWall | Piece::BlackWall => {
-                                    safety += 4;
-                                }
-                                Piece::WhiteCap | Piece::BlackCap => {
-                                    safety += 32;
-                                    mobility += 1;
-                                }
-                            }
-                        } else {
-                            match piece {
-                                Piece::WhiteFlat | Piece::BlackFlat => {
-                                    mobility += 2;
-                                }
-                                Piece::WhiteWall | Piece::BlackWall => {
-                                    safety -= 4;
-                                }
-                                Piece::WhiteCap | Piece::BlackCap => {
-                                    safety -= 32;
-                                }
-                            }
-                        }
-                    } else {
-                        mobility += 2;
-                    }
-                }
-                if mobility < 2 && !top.is_blocker() {
-                    friendly /= 2;
-                }
-                if safety < 0 {
-                    captive *= 2;
-                }
-                let stack_score = captive * c_mul + friendly * f_mul + pw;
-                if let Color::White = top.owner() {
-                    score += stack_score;
-                } else {
-                    score -= stack_score;
-                }
-            }
-        }
-
-        let black_c_lonely = (game.bits.cap & game.bits.black).adjacent() & (game.bits.flat | game.bits.wall);
-        if black_c_lonely == <Self::Game as TakBoard>::Bits::ZERO {
-            score += 30;
-        }
-
-        let white_c_lonely = (game.bits.cap & game.bits.white).adjacent() & (game.bits.flat | game.bits.wall);
-        if white_c_lonely == <Self::Game as TakBoard>::Bits::ZERO {
-            score -= 30;
-        }
-
-
-        // // Danger FOR the associated color
-        // const DANGER_MUL: i32 = 40; // 20
-        // let white_danger = (game.bits.road_pieces(Color::Black).critical_squares()
-        //     & !game.bits.empty())
-        // .pop_count() >= 1;
-        // let black_danger = (game.bits.road_pieces(Color::White).critical_squares()
-        //     & !game.bits.empty())
-        // .pop_count() >= 1;
-        let white_comp = connected_components(game.bits.road_pieces(Color::White));
-        let black_comp = connected_components(game.bits.road_pieces(Color::Black));
-        // Punish more components?
-        score -= white_comp.steps as i32 * self.connectivity;
-        score += black_comp.steps as i32 * self.connectivity;
-
-        // Examine the largest components
-        // let white_steps = simple_road_est::<Board6>(white_comp.bits);
-        // let black_steps = simple_road_est::<Board6>(black_comp.bits);
-        // if white_res < 16 || black_res < 16 {
-        //     // Half flats in white's favor
-        //     let mut flat_diff = (game.bits.flat & game.bits.white).pop_count() as i32
-        //         - (game.bits.flat & game.bits.black).pop_count() as i32;
-        //     flat_diff *= 2;
-        //     flat_diff -= game.komi() as i32;
-        //     let res_adv = white_res - black_res;
-        //     flat_diff += res_adv / 2;
-        //     if res_adv > 0 {
-        //         if flat_diff > 0 {
-        //             if white_res < 5 {
-        //                 score += flat_diff * 30;
-        //             } else {
-        //                 score += flat_diff * 20;
-        //             }
-        //         } else {
-        //             score += flat_diff * 10;
-        //         }
-        //     } else {
-        //         if flat_diff < 0 {
-        //             if black_res < 5 {
-        //                 score += flat_diff * 30;
-        //             } else {
-        //                 score += flat_diff * 20;
-        //             }
-        //         } else {
-        //             score += flat_diff * 10;
-        //         }
-        //     }
-        // }
-        // let white_connectivity = (game.bits.white.adjacent() & game.bits.white).pop_count();
-        // let black_connectivity = (game.bits.black.adjacent() & game.bits.black).pop_count();
-        // score += white_connectivity as i32 * self.connectivity;
-        // score -= black_connectivity as i32 * self.connectivity;
-        if let Color::White = game.side_to_move() {
-            // if white_danger {
-            //     score += DANGER_MUL * 2;
-            // }
-            // if black_danger {
-            //     score += DANGER_MUL;
-            // }
-            // if white_steps <= black_steps {
-            //     score += 60;
-            // } else {
-            //     score -= 60;
-            // }
-            // score -= DANGER_MUL * white_danger;
-            // score += DANGER_MUL * black_danger;
-            if depth % 2 == 0 {
-                score
-            } else {
-                score - self.tempo_offset
-            }
-        } else {
-            // if white_danger {
-            //     score += DANGER_MUL;
-            // }
-            // if black_danger {
-            //     score += DANGER_MUL * 2;
-            // }
-            // if black_steps <= white_steps {
-            //     score -= 60;
-            // } else {
-            //     score += 60;
-            // }
-            // score += DANGER_MUL * white_danger;
-            // score -= DANGER_MUL * black_danger;
-            if depth % 2 == 0 {
-                -1 * score
-            } else {
-                -1 * score + self.tempo_offset
-            }
-        }
-    }
-}
-
-fn simple_road_est<T: TakBoard>(bits: T::Bits) -> usize {
-    let north = repeat_slide(
-        bits,
-        T::Bits::north,
-        T::Bits::top(),
-    );
-
-    let east = repeat_slide(
-        bits,
-        T::Bits::east,
-        T::Bits::right(),
-    );
-
-    let south = repeat_slide(
-        bits,
-        T::Bits::south,
-        T::Bits::bottom(),
-    );
-
-    let west = repeat_slide(
-        bits,
-        T::Bits::west,
-        T::Bits::left(),
-    );
-
-    std::cmp::min(north.steps + south.steps, east.steps + west.steps)
-}
+pub const LOSE_SCORE: i32 = -WIN_SCORE;
+/// Scores beyond this are forced wins, WIN_SCORE less the plies until the game ends
+pub const WIN_THRESHOLD: i32 = WIN_SCORE - 1000;
+/// Default bonus for a capstone sitting directly on a friendly flat
+const CAP_SUPPORT: i32 = 30;
+/// Default score for a capstone with no flat or wall next to it
+const LONELY_CAP: i32 = -30;
 
 struct BitOutcome<B> {
+    /// The largest component, unused by the evaluation so far
+    #[allow(dead_code)]
     bits: B,
     steps: usize,
 }
@@ -337,20 +129,6 @@ impl<B> BitOutcome<B> {
 //     BitOutcome::new(b, counter)
 // }
 
-fn repeat_slide<B: Bitboard, F: Fn(B) -> B>(mut b: B, f: F, end: B) -> BitOutcome<B> {
-    let mut counter = 0;
-    let mut last = B::ZERO;
-    while b != last {
-        if b & end != B::ZERO {
-            break;
-        }
-        last = b;
-        b = f(b);
-        counter += 1;
-    }
-    BitOutcome::new(b, counter)
-}
-
 fn connected_components<B: Bitboard>(mut bits: B) -> BitOutcome<B> {
     let mut count = 0;
     let mut largest = B::ZERO;
@@ -366,6 +144,8 @@ fn connected_components<B: Bitboard>(mut bits: B) -> BitOutcome<B> {
     BitOutcome::new(largest, count)
 }
 
+/// Full recomputation of [`Stack::captive_friendly`], used to check the
+/// incrementally maintained counts in debug builds
 fn captive_friendly(stack: &Stack, top: Piece) -> (i32, i32) {
     let mut captive = 0;
     let mut friendly = 0;
@@ -380,55 +160,52 @@ fn captive_friendly(stack: &Stack, top: Piece) -> (i32, i32) {
     (captive, friendly)
 }
 
-pub struct Weights6 {
-    location: [i32; 36],
+/// Evaluation weights for any board size.
+///
+/// The defaults are derived from the 6x6 constants in [`Evaluator6`] and scaled
+/// to the board size by [`Weights::default`].
+#[derive(Clone)]
+pub struct Weights<T> {
+    location: Vec<i32>,
     connectivity: i32,
     tempo_offset: i32,
     piece: [i32; 3],
     stack_top: [i32; 6],
+    cap_support: i32,
+    lonely_cap: i32,
+    board: PhantomData<T>,
 }
 
-impl Weights6 {
+pub type Weights5 = Weights<Board5>;
+pub type Weights6 = Weights<Board6>;
+pub type Weights7 = Weights<Board7>;
+pub type Weights8 = Weights<Board8>;
+
+impl<T> Weights<T>
+where
+    T: TakBoard,
+{
     pub fn new(
-        location: [i32; 36],
+        location: Vec<i32>,
         connectivity: i32,
         tempo_offset: i32,
         piece: [i32; 3],
         stack_top: [i32; 6],
+        cap_support: i32,
+        lonely_cap: i32,
     ) -> Self {
+        assert_eq!(location.len(), T::SIZE * T::SIZE);
         Self {
             location,
             connectivity,
             tempo_offset,
             piece,
             stack_top,
+            cap_support,
+            lonely_cap,
+            board: PhantomData,
         }
     }
-    pub fn add_noise(&mut self) {
-        let mut seed: [u8; 32] = [0; 32];
-        getrandom::getrandom(&mut seed).unwrap();
-        let mut rng = Xoshiro256PlusPlus::from_seed(seed);
-        let offset = rng.next_u32() % 11 + 10;
-        let st_row = (rng.next_u32() % 6) as i32;
-        let st_col = (rng.next_u32() % 6) as i32;
-        // println!("{} @ ({}, {})", offset, st_col, st_row);
-        for row in 0..6 {
-            for col in 0..6 {
-                let idx = row * 6 + col;
-                let dist = (st_row - row).abs() + (st_col - col).abs();
-                let delta = offset as i32 - dist * dist;
-                if delta > 0 {
-                    self.location[idx as usize] += delta;
-                }
-            }
-        }
-        // for r in 0..6 {
-        //     println!("{:?}", &self.location[r * 6..(r + 1) * 6]);
-        // }
-        // for i in 0..self.location.len() {
-        //     self.location[i] += (rng.next_u32() % 4) as i32;
-        // }
-    }
     fn piece_weight(&self, p: Piece) -> i32 {
         match p {
             Piece::WhiteFlat | Piece::BlackFlat => self.piece[0],
@@ -443,28 +220,273 @@ impl Weights6 {
             Piece::WhiteCap | Piece::BlackCap => (self.stack_top[4], self.stack_top[5]),
         }
     }
+    /// Flattens all weights into a single parameter vector for tuning
+    pub(crate) fn params(&self) -> Vec<i32> {
+        let mut params = self.location.clone();
+        params.push(self.connectivity);
+        params.push(self.tempo_offset);
+        params.extend_from_slice(&self.piece);
+        params.extend_from_slice(&self.stack_top);
+        params.push(self.cap_support);
+        params.push(self.lonely_cap);
+        params
+    }
+    /// Inverse of [`Weights::params`]
+    pub(crate) fn from_params(params: &[i32]) -> Self {
+        let loc_len = T::SIZE * T::SIZE;
+        let (location, rest) = params.split_at(loc_len);
+        let mut piece = [0; 3];
+        piece.copy_from_slice(&rest[2..5]);
+        let mut stack_top = [0; 6];
+        stack_top.copy_from_slice(&rest[5..11]);
+        Self::new(location.to_vec(), rest[0], rest[1], piece, stack_top, rest[11], rest[12])
+    }
+    /// Scales a weight tuned for 6x6 to this board size
+    fn scale(weight: i32) -> i32 {
+        weight * 6 / T::SIZE as i32
+    }
 }
 
-impl Default for Weights6 {
+impl<T> Default for Weights<T>
+where
+    T: TakBoard,
+{
+    /// Flats are the unit of evaluation and keep their value on every size. The
+    /// other terms are scaled relative to 6x6: walls, caps and stacks are worth
+    /// less on larger boards where carries are longer and roads harder to block,
+    /// and every extra road component costs more on small boards.
     fn default() -> Self {
         let piece_arr = [
             Evaluator6::piece_weight(Piece::WhiteFlat),
-            Evaluator6::piece_weight(Piece::WhiteWall),
-            Evaluator6::piece_weight(Piece::WhiteCap),
+            Self::scale(Evaluator6::piece_weight(Piece::WhiteWall)),
+            Self::scale(Evaluator6::piece_weight(Piece::WhiteCap)),
         ];
         let st1 = Evaluator6::stack_top_multiplier(Piece::WhiteFlat);
         let st2 = Evaluator6::stack_top_multiplier(Piece::WhiteWall);
         let st3 = Evaluator6::stack_top_multiplier(Piece::WhiteCap);
+        let stack_top = [st1.0, st1.1, st2.0, st2.1, st3.0, st3.1];
         Self::new(
-            LOCATION_WEIGHT,
-            Evaluator6::CONNECTIVITY,
+            location_weights(T::SIZE),
+            Self::scale(Evaluator6::CONNECTIVITY),
             Evaluator6::TEMPO_OFFSET,
             piece_arr,
-            [st1.0, st1.1, st2.0, st2.1, st3.0, st3.1],
+            stack_top.map(Self::scale),
+            CAP_SUPPORT,
+            LONELY_CAP,
         )
     }
 }
 
+impl<T> Evaluator for Weights<T>
+where
+    T: TakBoard,
+{
+    type Game = T;
+    fn add_noise(&mut self) {
+        let mut seed: [u8; 32] = [0; 32];
+        getrandom::getrandom(&mut seed).unwrap();
+        let mut rng = Xoshiro256PlusPlus::from_seed(seed);
+        let size = T::SIZE as i32;
+        let offset = rng.next_u32() % 11 + 10;
+        let st_row = (rng.next_u32() % T::SIZE as u32) as i32;
+        let st_col = (rng.next_u32() % T::SIZE as u32) as i32;
+        for row in 0..size {
+            for col in 0..size {
+                let idx = row * size + col;
+                let dist = (st_row - row).abs() + (st_col - col).abs();
+                let delta = offset as i32 - dist * dist;
+                if delta > 0 {
+                    self.location[idx as usize] += delta;
+                }
+            }
+        }
+    }
+    #[inline(never)]
+    fn evaluate(&self, game: &Self::Game, depth: usize) -> i32 {
+        let mut score = Score(0);
+        self.incremental_terms(game, depth, &mut score);
+        debug_assert!(
+            game.stack_features() == &StackFeatures::build(game.board(), game.bits()),
+            "Stale stack features in {:?}",
+            game.to_tps()
+        );
+        debug_assert_eq!(score.0, {
+            let mut full = Score(0);
+            self.score_terms(game, depth, &mut full);
+            full.0
+        });
+        match game.side_to_move() {
+            Color::White => score.0,
+            Color::Black => -score.0,
+        }
+    }
+}
+
+impl<T> Weights<T>
+where
+    T: TakBoard,
+{
+    /// Evaluates the position term by term, with each term given from the
+    /// perspective of the color it is credited to
+    fn score_terms<S: TermSink>(&self, game: &T, depth: usize, sink: &mut S) {
+        // let white_res = game.pieces_reserve(Color::White) as i32;
+        // let black_res = game.pieces_reserve(Color::Black) as i32;
+        // let mut flat_diff = (game.bits().flat & game.bits().white).pop_count() as i32
+        //     - (game.bits().flat & game.bits().black).pop_count() as i32;
+        // flat_diff *= 2;
+        // flat_diff -= game.komi() as i32;
+        // let white_res_lead = black_res - white_res;
+        // let white_leading = white_res_lead >= 4 &&
+
+        // Only occupied squares contribute to the evaluation
+        let occupied = game.bits().white | game.bits().black;
+        for idx in BitIndexIterator::new(occupied) {
+            let stack = &game.board()[idx];
+            if stack.len() == 1 {
+                let top = *stack.last().unwrap();
+                let mut pw = self.piece_weight(top) + self.location[idx];
+                if top.is_cap() {
+                    pw += self.location[idx];
+                }
+                sink.add(top.owner(), EvalTerm::PieceLocation, Some(idx), pw);
+            } else if let Some(f) = StackFeature::compute(game.board(), game.bits(), idx) {
+                let top = f.top;
+                let pw = self.piece_weight(top) + self.location[idx];
+                debug_assert_eq!((f.captive, f.friendly), captive_friendly(stack, top));
+                let (c_mul, f_mul) = self.stack_top_multiplier(top);
+                let support = if f.support { self.cap_support } else { 0 };
+                let owner = top.owner();
+                sink.add(owner, EvalTerm::PieceLocation, Some(idx), pw);
+                sink.add(owner, EvalTerm::CapSupport, Some(idx), support);
+                sink.add(owner, EvalTerm::Captive, Some(idx), f.captive * c_mul);
+                sink.add(owner, EvalTerm::Friendly, Some(idx), f.friendly * f_mul);
+                // Immobile stacks only keep half of their friendly pieces
+                if f.immobile {
+                    sink.add(owner, EvalTerm::Immobile, Some(idx), -f.lost_friendly() * f_mul);
+                }
+                // Threatened stacks count their captives twice
+                if f.unsafe_stack {
+                    sink.add(owner, EvalTerm::Unsafe, Some(idx), f.captive * c_mul);
+                }
+            }
+        }
+        self.board_terms(game, depth, sink);
+    }
+    /// Sums the same terms as [`Weights::score_terms`], but takes the stack terms
+    /// from the features the board keeps up to date instead of visiting every
+    /// neighborhood, which leaves only a pass over the stack tops
+    fn incremental_terms<S: TermSink>(&self, game: &T, depth: usize, sink: &mut S) {
+        let bits = game.bits();
+        let features = game.stack_features();
+        for &color in [Color::White, Color::Black].iter() {
+            let own = bits.all_pieces(color);
+            let tops = [
+                (Piece::flat(color), bits.flat & own),
+                (Piece::wall(color), bits.wall & own),
+                (Piece::cap(color), bits.cap & own),
+            ];
+            for &(top, top_bits) in tops.iter() {
+                let mut pw = self.piece_weight(top) * top_bits.pop_count() as i32;
+                for idx in BitIndexIterator::new(top_bits) {
+                    pw += self.location[idx];
+                    // Lone capstones count their location twice
+                    if top.is_cap() && features.square(idx).is_none() {
+                        pw += self.location[idx];
+                    }
+                }
+                let (c_mul, f_mul) = self.stack_top_multiplier(top);
+                sink.add(color, EvalTerm::PieceLocation, None, pw);
+                sink.add(color, EvalTerm::Captive, None, features.captive(top) * c_mul);
+                sink.add(color, EvalTerm::Friendly, None, features.friendly(top) * f_mul);
+                sink.add(color, EvalTerm::Immobile, None, -features.lost_friendly(top) * f_mul);
+                sink.add(color, EvalTerm::Unsafe, None, features.unsafe_captive(top) * c_mul);
+            }
+            sink.add(color, EvalTerm::CapSupport, None, features.support(color) * self.cap_support);
+        }
+        self.board_terms(game, depth, sink);
+    }
+    /// Terms which depend on the whole board rather than on individual stacks
+    fn board_terms<S: TermSink>(&self, game: &T, depth: usize, sink: &mut S) {
+        let black_c_lonely = (game.bits().cap & game.bits().black).adjacent() & (game.bits().flat | game.bits().wall);
+        if black_c_lonely == T::Bits::ZERO {
+            sink.add(Color::Black, EvalTerm::LonelyCap, None, self.lonely_cap);
+        }
+
+        let white_c_lonely = (game.bits().cap & game.bits().white).adjacent() & (game.bits().flat | game.bits().wall);
+        if white_c_lonely == T::Bits::ZERO {
+            sink.add(Color::White, EvalTerm::LonelyCap, None, self.lonely_cap);
+        }
+
+
+        // // Danger FOR the associated color
+        // const DANGER_MUL: i32 = 40; // 20
+        // let white_danger = (game.bits().road_pieces(Color::Black).critical_squares()
+        //     & !game.bits().empty())
+        // .pop_count() >= 1;
+        // let black_danger = (game.bits().road_pieces(Color::White).critical_squares()
+        //     & !game.bits().empty())
+        // .pop_count() >= 1;
+        let white_comp = connected_components(game.bits().road_pieces(Color::White));
+        let black_comp = connected_components(game.bits().road_pieces(Color::Black));
+        // Punish more components?
+        let white_penalty = white_comp.steps as i32 * self.connectivity;
+        let black_penalty = black_comp.steps as i32 * self.connectivity;
+        sink.add(Color::White, EvalTerm::Components, None, -white_penalty);
+        sink.add(Color::Black, EvalTerm::Components, None, -black_penalty);
+
+        // Examine the largest components
+        // let white_steps = simple_road_est::<Board6>(white_comp.bits);
+        // let black_steps = simple_road_est::<Board6>(black_comp.bits);
+        // if white_res < 16 || black_res < 16 {
+        //     // Half flats in white's favor
+        //     let mut flat_diff = (game.bits().flat & game.bits().white).pop_count() as i32
+        //         - (game.bits().flat & game.bits().black).pop_count() as i32;
+        //     flat_diff *= 2;
+        //     flat_diff -= game.komi() as i32;
+        //     let res_adv = white_res - black_res;
+        //     flat_diff += res_adv / 2;
+        //     if res_adv > 0 {
+        //         if flat_diff > 0 {
+        //             if white_res < 5 {
+        //                 score += flat_diff * 30;
+        //             } else {
+        //                 score += flat_diff * 20;
+        //             }
+        //         } else {
+        //             score += flat_diff * 10;
+        //         }
+        //     } else {
+        //         if flat_diff < 0 {
+        //             if black_res < 5 {
+        //                 score += flat_diff * 30;
+        //             } else {
+        //                 score += flat_diff * 20;
+        //             }
+        //         } else {
+        //             score += flat_diff * 10;
+        //         }
+        //     }
+        // }
+        // let white_connectivity = (game.bits().white.adjacent() & game.bits().white).pop_count();
+        // let black_connectivity = (game.bits().black.adjacent() & game.bits().black).pop_count();
+        // score += white_connectivity as i32 * self.connectivity;
+        // score -= black_connectivity as i32 * self.connectivity;
+        // Odd depths always shift the score towards black
+        if depth % 2 == 1 {
+            match game.side_to_move() {
+                Color::White => sink.add(Color::White, EvalTerm::Tempo, None, -self.tempo_offset),
+                Color::Black => sink.add(Color::Black, EvalTerm::Tempo, None, self.tempo_offset),
+            }
+        }
+    }
+    /// Breaks the evaluation of a position down into its individual terms
+    pub fn trace(&self, game: &T, depth: usize) -> EvalTrace {
+        let mut trace = EvalTrace::new(T::SIZE, game.side_to_move());
+        self.score_terms(game, depth, &mut trace);
+        trace
+    }
+}
+
 // corner, offcorner, edge, edge, offcorner, corner,
 // offcorner, kosumi, ctouch, ctouch, kosumi, corner,
 // edge, ctouch, center, center, ctouch, edge,
@@ -474,14 +496,38 @@ impl Default for Weights6 {
 
 #[rustfmt::skip]
 const LOCATION_WEIGHT: [i32; 36] = [
-    00, 05, 05, 05, 05, 00,
-    05, 10, 15, 15, 10, 05,
-    05, 15, 20, 20, 15, 05,
-    05, 15, 20, 20, 15, 05,
-    05, 10, 15, 15, 10, 05,
-    00, 05, 05, 05, 05, 00,
+     0,  5,  5,  5,  5,  0,
+     5, 10, 15, 15, 10,  5,
+     5, 15, 20, 20, 15,  5,
+     5, 15, 20, 20, 15,  5,
+     5, 10, 15, 15, 10,  5,
+     0,  5,  5,  5,  5,  0,
 ];
 
+
+/// Builds a location table in the same shape as [`LOCATION_WEIGHT`]:
+/// corners are worthless, edges are slightly better and the interior
+/// grows towards the center until it saturates.
+fn location_weights(size: usize) -> Vec<i32> {
+    let mut location = Vec::with_capacity(size * size);
+    for row in 0..size {
+        for col in 0..size {
+            let row_dist = std::cmp::min(row, size - 1 - row) as i32;
+            let col_dist = std::cmp::min(col, size - 1 - col) as i32;
+            let weight = if row_dist == 0 && col_dist == 0 {
+                0
+            } else if row_dist == 0 || col_dist == 0 {
+                5
+            } else {
+                std::cmp::min(5 * (row_dist + col_dist), 20)
+            };
+            location.push(weight);
+        }
+    }
+    location
+}
+
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -491,7 +537,7 @@ mod test {
         let s = "x2,2,x2,1/x5,1/x,2,x,1,1,1/x,2,x2,1,x/x,2C,x4/x,2,x4 2 6";
         let mut board = Board6::try_from_tps(s).unwrap();
         let mut moves = Vec::new();
-        generate_all_moves(&mut board, &mut moves);
+        generate_all_moves(&board, &mut moves);
         let tak_threats = board.get_tak_threats(&moves, None);
         for m in tak_threats.iter() {
             dbg!(m.to_ptn::<Board6>());
@@ -499,6 +545,53 @@ mod test {
         assert_eq!(tak_threats.len(), 5);
     }
     #[test]
+    fn location_scaling() {
+        assert_eq!(location_weights(6), LOCATION_WEIGHT.to_vec());
+        let loc = location_weights(7);
+        assert_eq!(loc[0], 0);
+        assert_eq!(loc[3 * 7 + 3], 20);
+        assert_eq!(Weights7::default().location.len(), 49);
+    }
+    #[test]
+    fn trace_matches_evaluation() {
+        let eval = Weights6::default();
+        let positions = [
+            "2,1221122,1,1,1,2S/1,1,1,x,1C,1111212/x2,2,212,2C,11/2,2,x2,1,1/x3,1,1,x/x2,2,21,x,112S 2 32",
+            "x2,1,21,2,2/1,2,21,1,21,2/1S,2,2,2C,2,2/21S,1,121C,x,1,12/2,2,121,1,1,1/2,2,x3,22S 1 27",
+        ];
+        for tps in positions.iter() {
+            let board = Board6::try_from_tps(tps).unwrap();
+            for depth in 0..2 {
+                let trace = eval.trace(&board, depth);
+                assert_eq!(trace.score(), eval.evaluate(&board, depth));
+            }
+            let trace = eval.trace(&board, 0);
+            let squares: i32 = (0..36).map(|idx| trace.square(idx)).sum();
+            let global = [EvalTerm::LonelyCap, EvalTerm::Components];
+            let global: i32 = global
+                .iter()
+                .map(|&t| trace.term(Color::White, t) - trace.term(Color::Black, t))
+                .sum();
+            assert_eq!(squares + global, trace.total());
+        }
+    }
+    #[test]
+    fn sized_weights() {
+        use crate::board::{Board5, Board7};
+        let board = Board5::try_from_tps("2,x4/x5/x2,1C,x2/x5/x4,1 2 2").unwrap();
+        let mirror = Board5::try_from_tps("1,x4/x5/x2,2C,x2/x5/x4,2 1 2").unwrap();
+        let eval = Weights5::default();
+        assert_eq!(eval.evaluate(&board, 0), eval.evaluate(&mirror, 0));
+        assert!(eval.evaluate(&board, 0) < 0);
+
+        let mut board = Board7::try_from_tps("x7/x7/x7/x3,1,x3/x7/x7/2,x6 2 2").unwrap();
+        let eval = Weights7::default();
+        assert!(eval.evaluate(&board, 0) < 0);
+        let mut info = crate::search::SearchInfo::new(3, 10_000);
+        assert!(crate::search::search(&mut board, &eval, &mut info).is_some());
+        assert_eq!(info.hist_moves.board_size(), 7);
+    }
+    #[test]
     fn components() {
         let s = "2,x5/2,x,1,2,x2/x2,1,1,2C,x/x,2,2,1,x2/x3,1,x2/x5,1 1 7";
         let board = Board6::try_from_tps(s).unwrap();
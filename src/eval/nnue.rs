@@ -0,0 +1,437 @@
+use super::Evaluator;
+use crate::board::TakBoard;
+use crate::{Color, GameMove, Piece, RevGameMove, Stack};
+use anyhow::{bail, ensure, Result};
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"TZNN";
+const VERSION: u16 = 1;
+
+/// Top piece type, captive bucket and friendly bucket of a single square
+const FEATURES_PER_SQUARE: usize = 6 * 4 * 4;
+const NO_FEATURE: u16 = u16::MAX;
+/// Hidden activations are clipped to [0, ACTIVATION_MAX]
+const ACTIVATION_MAX: i32 = 255;
+/// The network output is divided by this to get a score in centiflats
+const OUTPUT_SCALE: i32 = 64;
+
+/// A small fully connected network with one hidden layer.
+///
+/// The input is one-hot per square: the top piece together with how many captives
+/// and friendlies (capped at 3) are buried under it. The hidden layer is the
+/// accumulator, which only needs to be touched for squares that change, and the
+/// output is a single score from white's perspective.
+///
+/// Weights are stored in a little endian binary file:
+///
+/// ```text
+/// magic "TZNN" | version u16 | size u8 | hidden u16
+/// input weights  i16 x (size * size * 96 * hidden), grouped by input feature
+/// input bias     i16 x hidden
+/// output weights i16 x hidden
+/// output bias    i32
+/// ```
+pub struct Network {
+    size: usize,
+    hidden: usize,
+    input_weights: Vec<i16>,
+    input_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+impl Network {
+    pub fn new(
+        size: usize,
+        hidden: usize,
+        input_weights: Vec<i16>,
+        input_bias: Vec<i16>,
+        output_weights: Vec<i16>,
+        output_bias: i32,
+    ) -> Result<Self> {
+        ensure!(
+            input_weights.len() == size * size * FEATURES_PER_SQUARE * hidden,
+            "Wrong number of input weights"
+        );
+        ensure!(input_bias.len() == hidden, "Wrong number of input biases");
+        ensure!(output_weights.len() == hidden, "Wrong number of output weights");
+        Ok(Self {
+            size,
+            hidden,
+            input_weights,
+            input_bias,
+            output_weights,
+            output_bias,
+        })
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::from_bytes(&data)
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= 9, "Network file is too short");
+        if &data[0..4] != MAGIC {
+            bail!("Not a network file");
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        ensure!(version == VERSION, "Unsupported network version {}", version);
+        let size = data[6] as usize;
+        let hidden = u16::from_le_bytes([data[7], data[8]]) as usize;
+        let num_inputs = size * size * FEATURES_PER_SQUARE * hidden;
+        let expected = 9 + 2 * (num_inputs + 2 * hidden) + 4;
+        ensure!(
+            data.len() == expected,
+            "Network file has {} bytes, expected {}",
+            data.len(),
+            expected
+        );
+        let mut values = data[9..data.len() - 4]
+            .chunks_exact(2)
+            .map(|c| i16::from_le_bytes([c[0], c[1]]));
+        let input_weights = values.by_ref().take(num_inputs).collect();
+        let input_bias = values.by_ref().take(hidden).collect();
+        let output_weights = values.by_ref().take(hidden).collect();
+        let tail = &data[data.len() - 4..];
+        let output_bias = i32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]);
+        Self::new(
+            size,
+            hidden,
+            input_weights,
+            input_bias,
+            output_weights,
+            output_bias,
+        )
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.push(self.size as u8);
+        data.extend_from_slice(&(self.hidden as u16).to_le_bytes());
+        let weights = self
+            .input_weights
+            .iter()
+            .chain(self.input_bias.iter())
+            .chain(self.output_weights.iter());
+        for w in weights {
+            data.extend_from_slice(&w.to_le_bytes());
+        }
+        data.extend_from_slice(&self.output_bias.to_le_bytes());
+        data
+    }
+    fn add_feature(&self, values: &mut [i32], input: usize) {
+        let weights = &self.input_weights[input * self.hidden..(input + 1) * self.hidden];
+        for (v, w) in values.iter_mut().zip(weights.iter()) {
+            *v += *w as i32;
+        }
+    }
+    fn sub_feature(&self, values: &mut [i32], input: usize) {
+        let weights = &self.input_weights[input * self.hidden..(input + 1) * self.hidden];
+        for (v, w) in values.iter_mut().zip(weights.iter()) {
+            *v -= *w as i32;
+        }
+    }
+    /// Score from white's perspective
+    fn output(&self, acc: &Accumulator) -> i32 {
+        let sum: i32 = acc
+            .values
+            .iter()
+            .zip(self.output_weights.iter())
+            .map(|(&v, &w)| v.clamp(0, ACTIVATION_MAX) * w as i32)
+            .sum();
+        (sum + self.output_bias) / OUTPUT_SCALE
+    }
+}
+
+fn square_feature(stack: &Stack) -> u16 {
+    let top = match stack.last() {
+        Some(top) => *top,
+        None => return NO_FEATURE,
+    };
+    let piece = match top {
+        Piece::WhiteFlat => 0,
+        Piece::WhiteWall => 1,
+        Piece::WhiteCap => 2,
+        Piece::BlackFlat => 3,
+        Piece::BlackWall => 4,
+        Piece::BlackCap => 5,
+    };
+    let (captive, friendly) = stack.captive_friendly();
+    (piece * 16 + captive.min(3) * 4 + friendly.min(3)) as u16
+}
+
+/// Hidden layer values along with the feature of every square they were built from
+struct Accumulator {
+    features: Vec<u16>,
+    values: Vec<i32>,
+}
+
+impl Accumulator {
+    fn new(net: &Network) -> Self {
+        Self {
+            features: vec![NO_FEATURE; net.size * net.size],
+            values: net.input_bias.iter().map(|&b| b as i32).collect(),
+        }
+    }
+    /// Builds the accumulator for a position from scratch
+    fn build<T: TakBoard>(net: &Network, game: &T) -> Self {
+        let mut acc = Self::new(net);
+        acc.rebuild(net, game);
+        acc
+    }
+    /// Recomputes the accumulator for a position in place
+    fn rebuild<T: TakBoard>(&mut self, net: &Network, game: &T) {
+        self.features.fill(NO_FEATURE);
+        for (v, &b) in self.values.iter_mut().zip(net.input_bias.iter()) {
+            *v = b as i32;
+        }
+        for idx in 0..game.board().len() {
+            self.refresh(net, game, idx);
+        }
+    }
+    /// Overwrites this accumulator with another without allocating
+    fn copy_from(&mut self, other: &Self) {
+        self.features.copy_from_slice(&other.features);
+        self.values.copy_from_slice(&other.values);
+    }
+    /// Brings the accumulator up to date after a move, refreshing only the
+    /// source square and the squares a spread dropped pieces on
+    fn update<T: TakBoard>(&mut self, net: &Network, game: &T, m: GameMove) {
+        self.refresh(net, game, m.src_index());
+        if m.is_stack_move() {
+            for qstep in m.quantity_iter(T::SIZE) {
+                self.refresh(net, game, qstep.index);
+            }
+        }
+    }
+    fn refresh<T: TakBoard>(&mut self, net: &Network, game: &T, idx: usize) {
+        let feature = square_feature(&game.board()[idx]);
+        let old = self.features[idx];
+        if feature == old {
+            return;
+        }
+        let base = idx * FEATURES_PER_SQUARE;
+        if old != NO_FEATURE {
+            net.sub_feature(&mut self.values, base + old as usize);
+        }
+        if feature != NO_FEATURE {
+            net.add_feature(&mut self.values, base + feature as usize);
+        }
+        self.features[idx] = feature;
+    }
+}
+
+/// Accumulators indexed by ply below the search root. Slots are allocated once
+/// and reused, so following a move only copies the parent into the next slot.
+struct AccumulatorStack {
+    slots: Vec<Accumulator>,
+    len: usize,
+}
+
+impl AccumulatorStack {
+    fn new(net: &Network) -> Self {
+        Self {
+            slots: (0..PREALLOCATED_PLIES).map(|_| Accumulator::new(net)).collect(),
+            len: 0,
+        }
+    }
+    fn last(&self) -> Option<&Accumulator> {
+        self.len.checked_sub(1).map(|idx| &self.slots[idx])
+    }
+    fn push<T: TakBoard>(&mut self, net: &Network, game: &T, m: GameMove) {
+        if self.len == self.slots.len() {
+            self.slots.push(Accumulator::new(net));
+        }
+        if self.len == 0 {
+            self.slots[0].rebuild(net, game);
+        } else {
+            let (parents, children) = self.slots.split_at_mut(self.len);
+            let acc = &mut children[0];
+            acc.copy_from(&parents[self.len - 1]);
+            acc.update(net, game, m);
+        }
+        self.len += 1;
+    }
+    fn pop(&mut self) {
+        self.len = self.len.saturating_sub(1);
+    }
+}
+
+/// Accumulator slots allocated up front, enough for any normal search depth
+const PREALLOCATED_PLIES: usize = 128;
+
+/// Evaluates positions with a [`Network`].
+///
+/// The evaluator keeps one accumulator per ply below the root of the search,
+/// pushed by [`Evaluator::do_move`] and popped by [`Evaluator::reverse_move`].
+/// Each one is a copy of its parent with only the squares touched by the move
+/// refreshed. Positions without an accumulator, such as the root, are evaluated
+/// from scratch. Since the accumulators live behind a `RefCell`, every search
+/// thread needs its own evaluator, though they may share the network.
+pub struct NnueEvaluator<T> {
+    net: Arc<Network>,
+    stack: RefCell<AccumulatorStack>,
+    board: PhantomData<T>,
+}
+
+impl<T: TakBoard> NnueEvaluator<T> {
+    pub fn new(net: Arc<Network>) -> Result<Self> {
+        ensure!(
+            net.size == T::SIZE,
+            "Network is for size {}, not {}",
+            net.size,
+            T::SIZE
+        );
+        Ok(Self {
+            stack: RefCell::new(AccumulatorStack::new(&net)),
+            net,
+            board: PhantomData,
+        })
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(Arc::new(Network::load(path)?))
+    }
+    /// Computes the accumulator from scratch, ignoring any incremental state
+    fn full_evaluate(&self, game: &T) -> i32 {
+        self.net.output(&Accumulator::build(&self.net, game))
+    }
+}
+
+impl<T: TakBoard> Clone for NnueEvaluator<T> {
+    fn clone(&self) -> Self {
+        Self {
+            net: self.net.clone(),
+            stack: RefCell::new(AccumulatorStack::new(&self.net)),
+            board: PhantomData,
+        }
+    }
+}
+
+impl<T: TakBoard> Evaluator for NnueEvaluator<T> {
+    type Game = T;
+    fn evaluate(&self, game: &Self::Game, _depth: usize) -> i32 {
+        let score = match self.stack.borrow().last() {
+            Some(acc) => self.net.output(acc),
+            None => self.full_evaluate(game),
+        };
+        debug_assert_eq!(score, self.full_evaluate(game));
+        match game.side_to_move() {
+            Color::White => score,
+            Color::Black => -score,
+        }
+    }
+    fn do_move(&self, game: &Self::Game, rev_move: RevGameMove) {
+        self.stack
+            .borrow_mut()
+            .push(&self.net, game, rev_move.game_move);
+    }
+    fn reverse_move(&self, _game: &Self::Game, _rev_move: RevGameMove) {
+        self.stack.borrow_mut().pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::Board5;
+    use crate::{generate_all_moves, Position};
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn random_network(size: usize, hidden: usize) -> Network {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        let mut gen = |n: usize| -> Vec<i16> {
+            (0..n).map(|_| (rng.next_u32() % 64) as i16 - 32).collect()
+        };
+        let input = gen(size * size * FEATURES_PER_SQUARE * hidden);
+        let bias = gen(hidden);
+        let output = gen(hidden);
+        Network::new(size, hidden, input, bias, output, 100).unwrap()
+    }
+
+    #[test]
+    fn network_round_trip() {
+        let net = random_network(5, 8);
+        let parsed = Network::from_bytes(&net.to_bytes()).unwrap();
+        assert_eq!(parsed.input_weights, net.input_weights);
+        assert_eq!(parsed.output_bias, net.output_bias);
+        let mut bytes = net.to_bytes();
+        bytes.pop();
+        assert!(Network::from_bytes(&bytes).is_err());
+        assert!(NnueEvaluator::<crate::board::Board6>::new(Arc::new(net)).is_err());
+    }
+
+    #[test]
+    fn incremental_matches_full() {
+        let eval = NnueEvaluator::<Board5>::new(Arc::new(random_network(5, 16))).unwrap();
+        let tps = "2,x3,1/x,2,12C,1,x/x,1,21C,x2/x,2,2,x2/x2,1,x2 1 10";
+        let mut board = Board5::try_from_tps(tps).unwrap();
+        eval.evaluate(&board, 0);
+        let mut moves = Vec::new();
+        generate_all_moves(&board, &mut moves);
+        for m in moves {
+            let rev = board.do_move(m);
+            eval.do_move(&board, rev);
+            assert_eq!(eval.evaluate(&board, 0), -eval.full_evaluate(&board));
+            let mut replies = Vec::new();
+            generate_all_moves(&board, &mut replies);
+            for reply in replies {
+                let rev_reply = board.do_move(reply);
+                eval.do_move(&board, rev_reply);
+                assert_eq!(eval.evaluate(&board, 0), eval.full_evaluate(&board));
+                board.reverse_move(rev_reply);
+                eval.reverse_move(&board, rev_reply);
+            }
+            board.reverse_move(rev);
+            eval.reverse_move(&board, rev);
+            assert_eq!(eval.evaluate(&board, 0), eval.full_evaluate(&board));
+        }
+        let mut info = crate::search::SearchInfo::new(3, 10_000);
+        assert!(crate::search::search(&mut board, &eval, &mut info).is_some());
+    }
+
+    #[test]
+    fn accumulator_stack_grows() {
+        let eval = NnueEvaluator::<Board5>::new(Arc::new(random_network(5, 4))).unwrap();
+        let mut board = Board5::start_position();
+        let mut moves = Vec::new();
+        let mut revs = Vec::new();
+        // Play past the preallocated slots, then take every move back
+        for _ in 0..PREALLOCATED_PLIES + 2 {
+            if board.game_result().is_some() {
+                break;
+            }
+            moves.clear();
+            generate_all_moves(&board, &mut moves);
+            let rev = board.do_move(moves[revs.len() % moves.len()]);
+            eval.do_move(&board, rev);
+            revs.push(rev);
+            let score = eval.evaluate(&board, 0);
+            assert_eq!(score, eval.full_evaluate(&board) * side_sign(&board));
+        }
+        while let Some(rev) = revs.pop() {
+            board.reverse_move(rev);
+            eval.reverse_move(&board, rev);
+            let score = eval.evaluate(&board, 0);
+            assert_eq!(score, eval.full_evaluate(&board) * side_sign(&board));
+        }
+        assert_eq!(eval.stack.borrow().len, 0);
+    }
+
+    fn side_sign(board: &Board5) -> i32 {
+        match board.side_to_move() {
+            Color::White => 1,
+            Color::Black => -1,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::Color;
+use std::fmt;
+
+/// The individual terms that make up the evaluation of [`super::Weights`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalTerm {
+    /// Piece weight plus location weight of every stack top
+    PieceLocation,
+    /// Bonus for a capstone sitting directly on a friendly flat
+    CapSupport,
+    /// Pieces of the opponent buried under a stack top
+    Captive,
+    /// Friendly pieces buried under a stack top
+    Friendly,
+    /// Friendly pieces which are discounted because the stack cannot move well
+    Immobile,
+    /// Captives which are counted twice because the stack is threatened
+    Unsafe,
+    /// Penalty for a capstone without any neighboring flats or walls
+    LonelyCap,
+    /// Penalty for every separate group of road pieces
+    Components,
+    /// Offset applied at odd search depths
+    Tempo,
+}
+
+impl EvalTerm {
+    pub const ALL: [EvalTerm; 9] = [
+        EvalTerm::PieceLocation,
+        EvalTerm::CapSupport,
+        EvalTerm::Captive,
+        EvalTerm::Friendly,
+        EvalTerm::Immobile,
+        EvalTerm::Unsafe,
+        EvalTerm::LonelyCap,
+        EvalTerm::Components,
+        EvalTerm::Tempo,
+    ];
+    fn name(self) -> &'static str {
+        match self {
+            EvalTerm::PieceLocation => "piece+location",
+            EvalTerm::CapSupport => "cap support",
+            EvalTerm::Captive => "captives",
+            EvalTerm::Friendly => "friendlies",
+            EvalTerm::Immobile => "immobile stacks",
+            EvalTerm::Unsafe => "unsafe stacks",
+            EvalTerm::LonelyCap => "lonely capstone",
+            EvalTerm::Components => "road components",
+            EvalTerm::Tempo => "tempo",
+        }
+    }
+}
+
+/// Receives every evaluation term as it is computed
+pub(crate) trait TermSink {
+    fn add(&mut self, color: Color, term: EvalTerm, square: Option<usize>, value: i32);
+}
+
+/// Sums the terms into a single score from white's perspective
+pub(crate) struct Score(pub i32);
+
+impl TermSink for Score {
+    #[inline(always)]
+    fn add(&mut self, color: Color, _term: EvalTerm, _square: Option<usize>, value: i32) {
+        match color {
+            Color::White => self.0 += value,
+            Color::Black => self.0 -= value,
+        }
+    }
+}
+
+/// Every term of an evaluation, per color and per square
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalTrace {
+    size: usize,
+    side_to_move: Color,
+    terms: [[i32; 9]; 2],
+    squares: Vec<i32>,
+}
+
+impl EvalTrace {
+    pub(crate) fn new(size: usize, side_to_move: Color) -> Self {
+        Self {
+            size,
+            side_to_move,
+            terms: [[0; 9]; 2],
+            squares: vec![0; size * size],
+        }
+    }
+    /// The value of a term from the perspective of the given color
+    pub fn term(&self, color: Color, term: EvalTerm) -> i32 {
+        self.terms[color as usize][term as usize]
+    }
+    /// The sum of all terms credited to the given color
+    pub fn color_total(&self, color: Color) -> i32 {
+        self.terms[color as usize].iter().sum()
+    }
+    /// Net value of all terms attributed to a square, from white's perspective
+    pub fn square(&self, idx: usize) -> i32 {
+        self.squares[idx]
+    }
+    /// The evaluation from white's perspective
+    pub fn total(&self) -> i32 {
+        self.color_total(Color::White) - self.color_total(Color::Black)
+    }
+    /// The evaluation from the side to move's perspective, as returned by the evaluator
+    pub fn score(&self) -> i32 {
+        match self.side_to_move {
+            Color::White => self.total(),
+            Color::Black => -self.total(),
+        }
+    }
+}
+
+impl TermSink for EvalTrace {
+    fn add(&mut self, color: Color, term: EvalTerm, square: Option<usize>, value: i32) {
+        self.terms[color as usize][term as usize] += value;
+        if let Some(idx) = square {
+            match color {
+                Color::White => self.squares[idx] += value,
+                Color::Black => self.squares[idx] -= value,
+            }
+        }
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16} {:>7} {:>7} {:>7}", "term", "white", "black", "net")?;
+        for &term in EvalTerm::ALL.iter() {
+            let white = self.term(Color::White, term);
+            let black = self.term(Color::Black, term);
+            writeln!(
+                f,
+                "{:<16} {:>7} {:>7} {:>7}",
+                term.name(),
+                white,
+                black,
+                white - black
+            )?;
+        }
+        let white = self.color_total(Color::White);
+        let black = self.color_total(Color::Black);
+        writeln!(f, "{:<16} {:>7} {:>7} {:>7}", "total", white, black, white - black)?;
+        writeln!(f, "Side to move score: {}", self.score())?;
+        writeln!(f, "Per square (white positive):")?;
+        for row in self.squares.chunks(self.size) {
+            for v in row {
+                write!(f, "{:>6}", v)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use super::Weights;
+use crate::board::TakBoard;
+use anyhow::{anyhow, bail, ensure, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Current version of the weights file format
+pub const WEIGHTS_VERSION: u32 = 1;
+
+/// A plain text file holding evaluation weights for one or more board sizes.
+///
+/// ```text
+/// version 1
+///
+/// [6]
+/// connectivity 20
+/// tempo_offset 150
+/// piece 100 40 80
+/// stack_top -50 60 -30 70 -20 90
+/// cap_support 30
+/// lonely_cap -30
+/// location
+///   0  5  5  5  5  0
+///   ...
+/// ```
+///
+/// Each key is followed by its values, which may span multiple lines. Anything
+/// after a `#` is a comment. Files written before `cap_support` and `lonely_cap`
+/// were added get the default values for them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightsFile {
+    version: u32,
+    sections: BTreeMap<usize, BTreeMap<String, Vec<i32>>>,
+}
+
+impl WeightsFile {
+    pub fn new() -> Self {
+        Self {
+            version: WEIGHTS_VERSION,
+            sections: BTreeMap::new(),
+        }
+    }
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read weights file {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut tokens = text
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap().split_whitespace())
+            .peekable();
+        ensure!(
+            tokens.next() == Some("version"),
+            "Weights file must start with a version"
+        );
+        let version: u32 = tokens
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| anyhow!("Unable to parse weights file version"))?;
+        ensure!(
+            version == WEIGHTS_VERSION,
+            "Unsupported weights file version {}, expected {}",
+            version,
+            WEIGHTS_VERSION
+        );
+        let mut sections = BTreeMap::new();
+        let mut current = None;
+        while let Some(token) = tokens.next() {
+            if token.starts_with('[') && token.ends_with(']') {
+                let size: usize = token[1..token.len() - 1]
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid section header {}", token))?;
+                ensure!(
+                    !sections.contains_key(&size),
+                    "Duplicate section for size {}",
+                    size
+                );
+                sections.insert(size, BTreeMap::new());
+                current = Some(size);
+                continue;
+            }
+            let size = current.ok_or_else(|| anyhow!("Found {} outside of a size section", token))?;
+            let section = sections.get_mut(&size).unwrap();
+            let mut values = Vec::new();
+            while let Some(value) = tokens.peek().and_then(|v| v.parse().ok()) {
+                values.push(value);
+                tokens.next();
+            }
+            if section.insert(token.to_string(), values).is_some() {
+                bail!("Duplicate key {}", token);
+            }
+        }
+        Ok(Self { version, sections })
+    }
+    /// Board sizes which have a section in this file
+    pub fn sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.sections.keys().copied()
+    }
+    pub fn weights<T: TakBoard>(&self) -> Result<Weights<T>> {
+        let section = self
+            .sections
+            .get(&T::SIZE)
+            .ok_or_else(|| anyhow!("No weights found for size {}", T::SIZE))?;
+        let get = |key: &str, len: usize| -> Result<&[i32]> {
+            let values = section
+                .get(key)
+                .ok_or_else(|| anyhow!("Missing {} for size {}", key, T::SIZE))?;
+            ensure!(
+                values.len() == len,
+                "Expected {} values for {}, found {}",
+                len,
+                key,
+                values.len()
+            );
+            Ok(values)
+        };
+        let get_or = |key: &str, default: i32| -> Result<i32> {
+            if section.contains_key(key) {
+                Ok(get(key, 1)?[0])
+            } else {
+                Ok(default)
+            }
+        };
+        let defaults = Weights::<T>::default();
+        let mut piece = [0; 3];
+        piece.copy_from_slice(get("piece", 3)?);
+        let mut stack_top = [0; 6];
+        stack_top.copy_from_slice(get("stack_top", 6)?);
+        Ok(Weights::new(
+            get("location", T::SIZE * T::SIZE)?.to_vec(),
+            get("connectivity", 1)?[0],
+            get("tempo_offset", 1)?[0],
+            piece,
+            stack_top,
+            get_or("cap_support", defaults.cap_support)?,
+            get_or("lonely_cap", defaults.lonely_cap)?,
+        ))
+    }
+    /// Adds or replaces the section for the weights' board size
+    pub fn set_weights<T: TakBoard>(&mut self, weights: &Weights<T>) {
+        let mut section = BTreeMap::new();
+        section.insert("location".to_string(), weights.location.clone());
+        section.insert("connectivity".to_string(), vec![weights.connectivity]);
+        section.insert("tempo_offset".to_string(), vec![weights.tempo_offset]);
+        section.insert("piece".to_string(), weights.piece.to_vec());
+        section.insert("stack_top".to_string(), weights.stack_top.to_vec());
+        section.insert("cap_support".to_string(), vec![weights.cap_support]);
+        section.insert("lonely_cap".to_string(), vec![weights.lonely_cap]);
+        self.sections.insert(T::SIZE, section);
+    }
+}
+
+impl Default for WeightsFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for WeightsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version {}", self.version)?;
+        for (size, section) in self.sections.iter() {
+            writeln!(f, "\n[{}]", size)?;
+            for (key, values) in section.iter() {
+                if key == "location" {
+                    writeln!(f, "{}", key)?;
+                    for row in values.chunks(*size) {
+                        for v in row {
+                            write!(f, " {:>4}", v)?;
+                        }
+                        writeln!(f)?;
+                    }
+                } else {
+                    write!(f, "{}", key)?;
+                    for v in values {
+                        write!(f, " {}", v)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: TakBoard> Weights<T> {
+    /// Loads the weights for this board size from a weights file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        WeightsFile::read(path)?.weights()
+    }
+    /// Writes the weights into their size section of a weights file, keeping
+    /// any sections for other board sizes that are already present
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = if path.exists() {
+            WeightsFile::read(path)?
+        } else {
+            WeightsFile::new()
+        };
+        file.set_weights(self);
+        file.write(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::{Board5, Board6};
+    use crate::eval::{Weights5, Weights6};
+    #[test]
+    fn round_trip() {
+        let mut file = WeightsFile::new();
+        file.set_weights(&Weights6::default());
+        file.set_weights(&Weights5::default());
+        let parsed = WeightsFile::parse(&file.to_string()).unwrap();
+        assert_eq!(parsed, file);
+        assert_eq!(parsed.sizes().collect::<Vec<_>>(), vec![5, 6]);
+        let weights: Weights<Board6> = parsed.weights().unwrap();
+        assert_eq!(weights.location, Weights6::default().location);
+        assert!(parsed.weights::<crate::board::Board7>().is_err());
+    }
+    #[test]
+    fn bad_files() {
+        assert!(WeightsFile::parse("version 2\n[6]").is_err());
+        assert!(WeightsFile::parse("[6]\npiece 1 2 3").is_err());
+        assert!(WeightsFile::parse("version 1\npiece 1 2 3").is_err());
+        let short = "version 1\n[5]\npiece 100 40 # missing cap\nlocation 1 2 3";
+        let file = WeightsFile::parse(short).unwrap();
+        assert!(file.weights::<Board5>().is_err());
+    }
+    #[test]
+    fn optional_keys() {
+        let mut file = WeightsFile::new();
+        let weights = Weights5 {
+            cap_support: 45,
+            ..Default::default()
+        };
+        file.set_weights(&weights);
+        let text = file.to_string();
+        assert!(text.contains("cap_support 45"));
+        let parsed: Weights<Board5> = WeightsFile::parse(&text).unwrap().weights().unwrap();
+        assert_eq!(parsed.params(), weights.params());
+        // Files from before the capstone keys fall back to the defaults
+        let old: String = text
+            .lines()
+            .filter(|l| !l.starts_with("cap_support") && !l.starts_with("lonely_cap"))
+            .map(|l| format!("{}\n", l))
+            .collect();
+        let parsed: Weights<Board5> = WeightsFile::parse(&old).unwrap().weights().unwrap();
+        assert_eq!(parsed.params(), Weights5::default().params());
+        let bad = text.replace("cap_support 45", "cap_support 45 46");
+        assert!(WeightsFile::parse(&bad).unwrap().weights::<Board5>().is_err());
+    }
+}
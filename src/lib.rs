@@ -8,23 +8,33 @@ pub mod transposition_table;
 pub mod eval;
 mod move_gen;
 pub mod search;
+pub mod selfplay;
+pub mod tablebase;
+pub mod tune;
 
-use crate::board::{Board5, Board6, Board7};
+use crate::board::{Board3, Board4, Board5, Board6, Board7, Board8};
 
+/// A game of any supported size. The boards are boxed since they differ a lot in size.
 #[non_exhaustive]
 pub enum TakGame {
-    Standard5(Board5),
-    Standard6(Board6),
-    Standard7(Board7),
+    Standard3(Box<Board3>),
+    Standard4(Box<Board4>),
+    Standard5(Box<Board5>),
+    Standard6(Box<Board6>),
+    Standard7(Box<Board7>),
+    Standard8(Box<Board8>),
 }
 
 impl TakGame {
     pub fn try_from_tps(tps: &str) -> Result<Self> {
         let size = tps.chars().filter(|&c| c == '/').count() + 1;
         match size {
-            5 => Ok(TakGame::Standard5(Board5::try_from_tps(tps)?)),
-            6 => Ok(TakGame::Standard6(Board6::try_from_tps(tps)?)),
-            7 => Ok(TakGame::Standard7(Board7::try_from_tps(tps)?)),
+            3 => Ok(TakGame::Standard3(Box::new(Board3::try_from_tps(tps)?))),
+            4 => Ok(TakGame::Standard4(Box::new(Board4::try_from_tps(tps)?))),
+            5 => Ok(TakGame::Standard5(Box::new(Board5::try_from_tps(tps)?))),
+            6 => Ok(TakGame::Standard6(Box::new(Board6::try_from_tps(tps)?))),
+            7 => Ok(TakGame::Standard7(Box::new(Board7::try_from_tps(tps)?))),
+            8 => Ok(TakGame::Standard8(Box::new(Board8::try_from_tps(tps)?))),
             _ => Err(anyhow!("Unknown game size: {}", size)),
         }
     }
@@ -37,9 +47,22 @@ pub enum TeiCommand {
     Go(String),
     Position(String),
     NewGame(usize),
+    Eval,
+    /// Reallocates the transposition table with the given size in megabytes
+    ResizeHash(usize),
+    /// Number of threads used by the following searches
+    Threads(usize),
+    /// Deepest iteration the following searches may reach
+    MaxDepth(usize),
+    /// The opponent played the move being pondered on
+    PonderHit,
+    /// Whether to name the expected reply to ponder on along with the best move
+    Ponder(bool),
+    /// Number of root moves the following searches report lines for
+    MultiPv(usize),
 }
 
-pub fn execute_moves_check_valid(board: &mut Board6, ptn_slice: &[&str]) -> Result<Vec<GameMove>> {
+pub fn execute_moves_check_valid<T: TakBoard>(board: &mut T, ptn_slice: &[&str]) -> Result<Vec<GameMove>> {
     let mut moves = Vec::new();
     let mut made_moves = Vec::new();
     for m_str in ptn_slice {
@@ -149,6 +149,39 @@ impl GameMove {
     pub fn null_move() -> Self {
         Self(0)
     }
+    /// Returns the raw bits of the move, for packing it into other data structures
+    pub(crate) fn raw(self) -> u32 {
+        self.0
+    }
+    /// Inverse of [`GameMove::raw`]. No validation is performed, so the move must be
+    /// checked for legality before it is played.
+    pub(crate) fn from_raw(bits: u32) -> Self {
+        Self(bits)
+    }
+    /// Returns the move in 24 bits, for tables where space is tight.
+    ///
+    /// The low 21 bits are kept as they are. Placement moves have no slide or crush,
+    /// so the top 3 bits hold the placed piece, 7 for a crushing stack move, or 0 for
+    /// any other stack move.
+    pub(crate) fn compact(self) -> u32 {
+        let tag = if self.is_place_move() {
+            (self.0 >> 24) & 0x7
+        } else if self.crush() {
+            0x7
+        } else {
+            0
+        };
+        (self.0 & 0x1FFFFF) | (tag << 21)
+    }
+    /// Inverse of [`GameMove::compact`]. Like [`GameMove::from_raw`], no validation is performed.
+    pub(crate) fn from_compact(bits: u32) -> Self {
+        let low = bits & 0x1FFFFF;
+        match bits >> 21 {
+            0 => Self(low),
+            0x7 => Self(low | 0x800000),
+            piece => Self(low | (piece << 24)),
+        }
+    }
     /// Returns true if this is a placement move, i.e. not a stack move or null move.
     pub fn is_place_move(self) -> bool {
         (self.0 & Self::PLACEMENT_BITS) > 0
@@ -319,7 +352,7 @@ impl Iterator for StackMoveIterator {
             return None;
         }
         let bit = self.slide_bits & 1;
-        self.slide_bits = self.slide_bits >> 1;
+        self.slide_bits >>= 1;
         let index = self.index;
         if bit != 0 {
             // Move
@@ -368,7 +401,7 @@ impl Iterator for QuantityMoveIterator {
             return None;
         }
         let quantity = self.slide_bits & 0xF;
-        self.slide_bits = self.slide_bits >> 4;
+        self.slide_bits >>= 4;
         match self.direction {
             0 => self.index -= self.board_size,
             1 => self.index += 1,
@@ -427,7 +460,7 @@ impl Iterator for RevStackMoveIterator {
         }
         // Drop the high bits instead of the low bits
         let bits = self.slide_bits & 0x8000_0000;
-        self.slide_bits = self.slide_bits << 1;
+        self.slide_bits <<= 1;
         if bits != 0 && self.dirty {
             // Reversed from the normal direction step, since we're going backwards
             match self.direction {
@@ -774,15 +807,29 @@ mod test {
         assert_eq!(moves.len(), 190);
     }
 
+    #[test]
+    pub fn compact_moves() {
+        let s = "1,1,2212S,x3/x2,1,x,1,x/2,211212C,11112,2,1S,22112S/221,x,221C,1,x,2/2,2,1,1,1,2/2,x,1,x2,2 2 35";
+        let board = Board6::try_from_tps(s).unwrap();
+        let mut moves = all_moves_allocate(&board);
+        moves.push(GameMove::try_from_ptn_m("8a1+1111112", 8, Color::White).unwrap());
+        moves.push(GameMove::try_from_ptn_m("Ch8", 8, Color::Black).unwrap());
+        moves.push(GameMove::null_move());
+        for m in moves {
+            assert!(m.compact() < 1 << 24);
+            assert_eq!(GameMove::from_compact(m.compact()), m);
+        }
+    }
+
     #[test]
     pub fn quantity_move_test() {
         let ptn = "7a5>1231";
         let m = GameMove::try_from_ptn_m(ptn, 7, Color::White).unwrap();
-        let mut iter = m.quantity_iter(7);
+        let iter = m.quantity_iter(7);
         let st = m.src_index();
         let mut counter = 1;
         let vals = &[0, 1, 2, 3, 1];
-        while let Some(qstep) = iter.next() {
+        for qstep in iter {
             assert_eq!(qstep.index, st + counter); // True because > direction
             assert_eq!(qstep.quantity, vals[counter]);
             counter += 1;
@@ -8,19 +8,19 @@ pub struct SmartMoveBuffer {
 }
 
 impl SmartMoveBuffer {
-    pub fn new() -> Self {
+    pub fn new(board_size: usize) -> Self {
         Self {
             moves: Vec::new(),
-            stack_hist: vec![0; 36],
+            stack_hist: vec![0; board_size * board_size],
             queries: 0,
         }
     }
-    pub fn score_stack_moves<T: TakBoard>(&mut self, board: &T, last_capture: Option<RevGameMove>) {
+    pub fn score_stack_moves<T: TakBoard>(&mut self, board: &T, _last_capture: Option<RevGameMove>) {
         // let DEBUG: &'static str = "5b2>221";
         let active_side = board.side_to_move();
         let mut stack_idx = usize::MAX;
         // Moves should be grouped by src index due to generator impl
-        let mut stack_data = [Piece::WhiteFlat; 8]; // Todo T::SIZE + 1 when rust figures out this valid
+        let mut stack_data = [Piece::WhiteFlat; 9]; // Todo T::SIZE + 1 when rust figures out this valid
         for x in self.moves.iter_mut().filter(|x| x.mv.is_stack_move()) {
             let mut score = 0;
             let src_idx = x.mv.src_index();
@@ -168,6 +168,10 @@ impl SmartMoveBuffer {
             self.moves.remove(pos);
         }
     }
+    /// Keeps only the moves for which the predicate returns true
+    pub fn retain<F: Fn(GameMove) -> bool>(&mut self, f: F) {
+        self.moves.retain(|m| f(m.mv));
+    }
     pub fn score_pv_move(&mut self, pv_move: GameMove) {
         if let Some(found) = self.moves.iter_mut().find(|m| m.mv == pv_move) {
             found.score += 250;
@@ -187,6 +191,18 @@ impl SmartMoveBuffer {
             }
         }
     }
+    /// Adds a pseudo random offset of up to 3 to every score, so that lazy SMP
+    /// helpers with different seeds visit similarly scored moves in different orders
+    pub fn perturb(&mut self, seed: u64) {
+        for m in self.moves.iter_mut() {
+            // Splitmix64 finalizer
+            let mut x = seed ^ (m.mv.raw() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            x ^= x >> 31;
+            m.score += (x % 4) as i16;
+        }
+    }
     pub fn get_best(&mut self, ply: usize, info: &SearchInfo) -> GameMove {
         if self.queries <= 16 {
             self.queries += 1;
@@ -287,15 +303,20 @@ impl KillerMoves {
 
 #[derive(Clone)]
 pub struct HistoryMoves {
+    board_size: usize,
     vec: Vec<u32>,
 }
 
 impl HistoryMoves {
     pub fn new(board_size: usize) -> Self {
         Self {
+            board_size,
             vec: vec![1; board_size * board_size * 4],
         }
     }
+    pub fn board_size(&self) -> usize {
+        self.board_size
+    }
     pub fn update(&mut self, depth: usize, mv: GameMove) {
         let value = depth as u32;
         self.vec[mv.direction() as usize + mv.src_index() * 4] += value * value;
@@ -317,7 +338,7 @@ mod test {
     fn big_stack_order() {
         let tps = "21C,222222,2,x3/2,2,2S,12121S,x,2/2,2,1,1,1,1/x,1S,111112C,1,1,x/1,12112S,x4/x,2,x3,1 2 31";
         let board = Board6::try_from_tps(tps).unwrap();
-        let mut moves = SmartMoveBuffer::new();
+        let mut moves = SmartMoveBuffer::new(Board6::SIZE);
         generate_all_moves(&board, &mut moves);
         moves.score_stack_moves(&board, None);
         moves.moves.sort_by_key(|x| -x.score);
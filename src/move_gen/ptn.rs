@@ -93,9 +93,8 @@ impl GameMove {
                 }
             };
             game_move = game_move.set_direction(dir);
-            let mut tile_counter = 1;
             let mut total_pieces = 0;
-            while let Some(num) = split.next() {
+            for (tile_counter, num) in (1..).zip(split.by_ref()) {
                 game_move = match tile_counter {
                     1..=7 => {
                         let pieces = num.parse().ok()?;
@@ -104,7 +103,6 @@ impl GameMove {
                     }
                     _ => return None,
                 };
-                tile_counter += 1;
             }
             if let Some(p) = board.index(dest).last() {
                 if p.is_wall() {
@@ -155,7 +153,7 @@ impl GameMove {
 
         if let Some(dir) = iter.next() {
             // Stack Move
-            let pieces = pieces.unwrap_or(1) as u32;
+            let pieces = pieces.unwrap_or(1);
             let dir = match dir {
                 '+' => 0,
                 '>' => 1,
@@ -170,7 +168,7 @@ impl GameMove {
                 .chain_crush(crush)
                 .set_index(square as u32);
             let mut counter = 0;
-            while let Some(ch) = iter.next() {
+            for ch in iter {
                 if let Some(num) = ch.to_digit(16) {
                     mv = mv.set_next_tile(num);
                     // let value = (num as u64) << (4 * counter);
@@ -227,6 +225,7 @@ fn tile_ptn<T: TakBoard>(index: usize) -> String {
         4 => "e",
         5 => "f",
         6 => "g",
+        7 => "h",
         _ => unimplemented!(),
     };
     let row = size - row;
@@ -251,6 +250,17 @@ mod test {
         }
     }
     #[test]
+    pub fn size8_ptn() {
+        let s = "x8/x8/x8/x8/x8/x8/x8/x7,21212121C 1 20";
+        let board = crate::board::Board8::try_from_tps(s).unwrap();
+        for p in &["Ch8", "a1", "Sh1", "8h1+", "8h1<1111112", "5h1+23"] {
+            let m = GameMove::try_from_ptn(p, &board).unwrap();
+            assert_eq!(p, &m.to_ptn::<crate::board::Board8>());
+            let pt = m.to_playtak::<crate::board::Board8>();
+            assert_eq!(GameMove::try_from_playtak(&pt, &board), Some(m));
+        }
+    }
+    #[test]
     pub fn playtak_move() {
         let s = "2,2,2,21,12,x/x4,2,x/x4,2C,x/1,2,12,122211C,x2/x2,1S,1,12,1/x3,2S,1,1 1 19";
         let board = Board6::try_from_tps(s).unwrap();
@@ -1,8 +1,9 @@
 use super::{Color, GameResult};
 use crate::board::TakBoard;
 use crate::eval::Evaluator;
-use crate::eval::{LOSE_SCORE, WIN_SCORE};
-use crate::transposition_table::{HashEntry, HashTable, ScoreCutoff};
+use crate::eval::{LOSE_SCORE, WIN_SCORE, WIN_THRESHOLD};
+use crate::tablebase::Tablebase;
+use crate::transposition_table::{HashEntry, HashTable, Probe, ScoreCutoff, Store, MAX_TT_DEPTH};
 use crate::move_gen::{
     generate_aggressive_place_moves, generate_all_stack_moves, GameMove, HistoryMoves, KillerMoves,
     MoveBuffer, RevGameMove, SmartMoveBuffer,
@@ -10,6 +11,8 @@ use crate::move_gen::{
 use crate::TeiCommand;
 use crossbeam_channel::Receiver;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 pub mod proof;
@@ -33,6 +36,9 @@ const PV_RE_SEARCH_NON_PV: bool = true; // stockfish doesn't... ONLY DISABLE WHE
 const ASPIRATION_ENABLED: bool = false; // TERRIBLE performance, search not stable enough
 const ASPIRATION_WINDOW: i32 = 55;
 
+// time management parameters
+const STABLE_DEPTHS: usize = 3; // once the best move survives this many depths, halve the soft limit
+
 // move generation and ordering parameters
 const GEN_THOROUGH_ORDER_DEPTH: usize = 1; // where to stop bothering with accurate move ordering
 
@@ -47,32 +53,60 @@ const IID_DIVISION: usize = 2;
 pub struct SearchInfo {
     pub max_depth: usize,
     pub nodes: usize,
-    pv_table: HashTable,
+    pv_table: Arc<HashTable>,
     pub killer_moves: Vec<KillerMoves>,
     pub hist_moves: HistoryMoves,
     stopped: bool,
     input: Option<Receiver<TeiCommand>>,
-    max_time: u64,
+    interrupted: bool,
+    pending: Vec<TeiCommand>,
+    pondering: bool,
+    soft_time: u64, // milliseconds after which no new depth is started
+    hard_time: u64, // milliseconds after which the search is aborted
+    max_nodes: Option<usize>,
+    quiet: bool,
     start_time: Instant,
     start_ply: usize,
     estimate_time: bool,
+    tablebase: Option<Arc<Tablebase>>,
+    stop_signal: Option<Arc<AtomicBool>>,
+    root_moves: Vec<GameMove>,
+    root_best: Option<GameMove>,
+    multi_pv: usize,
+    excluded_root: Vec<GameMove>,
+    order_seed: u64, // Nonzero for lazy SMP helpers, which perturb their move ordering
     pub stats: SearchStats,
 }
 
 impl SearchInfo {
     pub fn new(max_depth: usize, pv_size: usize) -> Self {
+        // Deeper entries would not fit in the transposition table
+        let max_depth = std::cmp::min(max_depth, MAX_TT_DEPTH);
         Self {
             max_depth,
-            pv_table: HashTable::new(pv_size),
+            pv_table: Arc::new(HashTable::new(pv_size)),
             killer_moves: vec![KillerMoves::new(); max_depth + 1],
-            hist_moves: HistoryMoves::new(6), // Todo init in search
+            hist_moves: HistoryMoves::new(0), // Sized to the board in search
             nodes: 0,
             stopped: false,
             input: None,
-            max_time: 120, // Some large but not enormous default
+            interrupted: false,
+            pending: Vec::new(),
+            pondering: false,
+            soft_time: 120_000, // Some large but not enormous default
+            hard_time: 120_000,
+            max_nodes: None,
+            quiet: false,
             start_time: Instant::now(),
             start_ply: 0,
             estimate_time: true,
+            tablebase: None,
+            stop_signal: None,
+            root_moves: Vec::new(),
+            root_best: None,
+            multi_pv: 1,
+            excluded_root: Vec::new(),
+            order_seed: 0,
             stats: SearchStats::new(16),
         }
     }
@@ -82,45 +116,187 @@ impl SearchInfo {
             self.stats.fail_high, self.stats.fail_high_first, self.stats.transposition_cutoffs
         );
     }
-    pub fn take_table(mut self, other: &mut Self) -> Self {
-        std::mem::swap(&mut self.pv_table, &mut other.pv_table);
+    /// Uses the given transposition table instead of this search's own, so that it
+    /// can be kept across searches or shared with other concurrent searches
+    pub fn shared_table(mut self, table: Arc<HashTable>) -> Self {
+        self.pv_table = table;
         self
     }
+    /// A handle to the transposition table used by this search
+    pub fn table(&self) -> Arc<HashTable> {
+        self.pv_table.clone()
+    }
     pub fn set_start_ply(&mut self, start_ply: usize) {
         self.start_ply = start_ply;
     }
+    /// Commands arriving on this stream interrupt the search, apart from ponderhit.
+    /// Anything other than stop is kept for the caller, see [`SearchInfo::take_pending`].
     pub fn input_stream(mut self, r: Receiver<TeiCommand>) -> Self {
         self.input = Some(r);
         self
     }
+    /// Stops the search after this many milliseconds
     pub fn max_time(mut self, time: u64) -> Self {
-        self.max_time = time;
+        self.soft_time = time;
+        self.hard_time = time;
+        self
+    }
+    /// No new depth is started once `soft` milliseconds have passed, or sooner if the
+    /// best move is stable, and the search is aborted after `hard` milliseconds
+    pub fn time_limits(mut self, soft: u64, hard: u64) -> Self {
+        self.soft_time = soft;
+        self.hard_time = std::cmp::max(soft, hard);
+        self
+    }
+    /// Searches without a time limit until a ponderhit arrives on the input stream.
+    /// The time limits then apply as if the search had started at the ponderhit.
+    pub fn ponder(mut self, ponder: bool) -> Self {
+        self.pondering = ponder;
+        self
+    }
+    /// Whether the search is still waiting for a ponderhit
+    pub fn pondering(&self) -> bool {
+        self.pondering
+    }
+    fn ponder_hit(&mut self) {
+        let elapsed = self.elapsed_ms();
+        self.pondering = false;
+        self.soft_time = self.soft_time.saturating_add(elapsed);
+        self.hard_time = self.hard_time.saturating_add(elapsed);
+    }
+    /// Stops the search once roughly this many nodes have been searched
+    pub fn max_nodes(mut self, nodes: usize) -> Self {
+        self.max_nodes = Some(nodes);
+        self
+    }
+    /// Suppresses the per depth info lines
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+    /// Only these moves are considered at the root. An empty list allows every move.
+    pub fn root_moves(mut self, moves: Vec<GameMove>) -> Self {
+        self.root_moves = moves;
+        self
+    }
+    fn root_allowed(&self, is_root: bool, m: GameMove) -> bool {
+        !is_root
+            || ((self.root_moves.is_empty() || self.root_moves.contains(&m))
+                && !self.excluded_root.contains(&m))
+    }
+    /// Searches the best `lines` root moves at every depth instead of only the best
+    /// one, each with its own score and principal variation
+    pub fn multi_pv(mut self, lines: usize) -> Self {
+        self.multi_pv = std::cmp::max(lines, 1);
         self
     }
+    /// Solved positions are scored from the tablebase instead of being searched
+    pub fn tablebase(mut self, tablebase: Arc<Tablebase>) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+    /// Info for the lazy SMP helper thread with the given index, which shares the
+    /// transposition table and time limit of this search but stops once the signal
+    /// is raised
+    fn helper(&self, idx: usize, stop_signal: Arc<AtomicBool>) -> Self {
+        let mut helper = SearchInfo::new(self.max_depth, 0).quiet(true);
+        helper.pv_table = self.pv_table.clone();
+        helper.soft_time = self.soft_time;
+        helper.hard_time = self.hard_time;
+        helper.start_time = self.start_time;
+        helper.estimate_time = false;
+        helper.tablebase = self.tablebase.clone();
+        helper.stop_signal = Some(stop_signal);
+        helper.root_moves = self.root_moves.clone();
+        helper.order_seed = idx as u64;
+        // Helpers never see the ponderhit, so they run until the main search stops them
+        helper.pondering = self.pondering;
+        helper
+    }
     pub fn start_search(&mut self) {
         self.stopped = false;
+        self.interrupted = false;
         self.nodes = 0;
         self.start_time = Instant::now();
     }
+    fn elapsed_ms(&self) -> u64 {
+        self.start_time.elapsed().as_millis() as u64
+    }
     pub fn take_input_stream(&mut self) -> Option<Receiver<TeiCommand>> {
         self.input.take()
     }
+    /// Whether the search was cut short by a command on the input stream
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+    /// Commands received during the search that still need to be handled
+    pub fn take_pending(&mut self) -> Vec<TeiCommand> {
+        std::mem::take(&mut self.pending)
+    }
     pub fn check_stop(&mut self) {
-        let secs = self.start_time.elapsed().as_secs();
-        if secs >= self.max_time {
+        if !self.pondering && self.elapsed_ms() >= self.hard_time {
             self.stopped = true;
         }
-        if let Some(ref r) = self.input {
-            if r.try_recv().is_ok() {
+        if let Some(max_nodes) = self.max_nodes {
+            if self.nodes >= max_nodes {
+                self.stopped = true;
+            }
+        }
+        let received: Vec<_> = match self.input {
+            Some(ref r) => r.try_iter().collect(),
+            None => Vec::new(),
+        };
+        for command in received {
+            match command {
+                TeiCommand::PonderHit => {
+                    if self.pondering {
+                        self.ponder_hit();
+                    }
+                }
+                TeiCommand::Stop => {
+                    self.stopped = true;
+                    self.interrupted = true;
+                }
+                command @ (TeiCommand::Quit
+                | TeiCommand::Go(_)
+                | TeiCommand::Position(_)
+                | TeiCommand::NewGame(_)) => {
+                    self.stopped = true;
+                    self.interrupted = true;
+                    self.pending.push(command);
+                }
+                // Option changes only apply to the following searches
+                command => self.pending.push(command),
+            }
+        }
+        if let Some(ref signal) = self.stop_signal {
+            if signal.load(Ordering::Relaxed) {
                 self.stopped = true;
             }
         }
     }
     fn store_move<E: TakBoard>(&mut self, position: &E, entry: HashEntry) {
-        self.pv_table.put(position.hash(), entry);
+        let ply_depth = self.ply_depth(position) as i32;
+        let entry = entry.map_score(|score| score_to_tt(score, ply_depth));
+        self.stats.tt_stores += 1;
+        if let Store::Overwrite = self.pv_table.put(position.hash(), entry) {
+            self.stats.tt_overwrites += 1;
+        }
     }
-    fn lookup_move<E: TakBoard>(&mut self, position: &E) -> Option<&HashEntry> {
-        self.pv_table.get(&position.hash())
+    fn lookup_move<E: TakBoard>(&mut self, position: &E) -> Option<HashEntry> {
+        self.stats.tt_probes += 1;
+        match self.pv_table.probe(position.hash()) {
+            Probe::Hit(entry) => {
+                self.stats.tt_hits += 1;
+                let ply_depth = self.ply_depth(position) as i32;
+                Some(entry.map_score(|score| score_from_tt(score, ply_depth)))
+            }
+            Probe::Miss => None,
+            Probe::Collision => {
+                self.stats.tt_collisions += 1;
+                None
+            }
+        }
     }
     pub fn pv_move<E: TakBoard>(&mut self, position: &E) -> Option<GameMove> {
         self.pv_table.get(&position.hash()).map(|e| e.game_move)
@@ -142,19 +318,87 @@ impl SearchInfo {
         }
         forward
     }
+    /// The principal variation starting with the given root move, which may differ
+    /// from the table's if the root entry was kept from an earlier, deeper search
+    fn pv_from<E: TakBoard>(&mut self, position: &mut E, first: GameMove) -> Vec<GameMove> {
+        let rev = position.do_move(first);
+        let mut pv = vec![first];
+        pv.extend(self.full_pv(position));
+        position.reverse_move(rev);
+        pv
+    }
     fn ply_depth<E: TakBoard>(&self, position: &E) -> usize {
-        position.ply() - self.start_ply
+        // A null move by black at the root steps the ply back below the start ply
+        position.ply().saturating_sub(self.start_ply)
     }
     pub fn clear_tt(&mut self) {
         self.pv_table.clear();
     }
 }
 
+/// Win scores count plies from the root, but a table entry may be reached at any
+/// ply and from other roots, so the table counts them from the entry's position
+fn score_to_tt(score: i32, ply_depth: i32) -> i32 {
+    if score > WIN_THRESHOLD {
+        score + ply_depth
+    } else if score < -WIN_THRESHOLD {
+        score - ply_depth
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply_depth: i32) -> i32 {
+    if score > WIN_THRESHOLD {
+        score - ply_depth
+    } else if score < -WIN_THRESHOLD {
+        score + ply_depth
+    } else {
+        score
+    }
+}
+
+/// Moves until a forced end of the game, positive if the side to move wins,
+/// or None if the score is not a forced result
+pub fn mate_moves(score: i32) -> Option<i32> {
+    if score > WIN_THRESHOLD {
+        Some((WIN_SCORE - score + 1) / 2)
+    } else if score < -WIN_THRESHOLD {
+        Some(-(score - LOSE_SCORE + 1) / 2)
+    } else {
+        None
+    }
+}
+
+/// A score as TEI reports it, either `cp <centiflats>` or `mate <moves>`
+pub struct TeiScore(pub i32);
+
+impl std::fmt::Display for TeiScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match mate_moves(self.0) {
+            Some(moves) => write!(f, "mate {}", moves),
+            None => write!(f, "cp {}", self.0),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SearchStats {
     fail_high: u64,
     fail_high_first: u64,
     transposition_cutoffs: u64,
+    pub tablebase_hits: u64,
+    /// Transposition table lookups, and how many of them found the position
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    /// Lookups which missed while every entry in the bucket held another position
+    pub tt_collisions: u64,
+    pub tt_stores: u64,
+    /// Stores which evicted the entry of another position
+    pub tt_overwrites: u64,
+    /// Sampled entries by how many plies before the root they were stored, filled in
+    /// once the search is over
+    pub tt_ages: Vec<usize>,
     ordering_cut: Vec<usize>,
     ordering_alpha: Vec<usize>,
     bad_search: u64,
@@ -166,6 +410,13 @@ impl SearchStats {
             fail_high: 0,
             fail_high_first: 0,
             transposition_cutoffs: 0,
+            tablebase_hits: 0,
+            tt_probes: 0,
+            tt_hits: 0,
+            tt_collisions: 0,
+            tt_stores: 0,
+            tt_overwrites: 0,
+            tt_ages: Vec::new(),
             ordering_cut: vec![0; ordering_size],
             ordering_alpha: vec![0; ordering_size],
             bad_search: 0,
@@ -187,10 +438,18 @@ impl SearchStats {
     }
 }
 
+/// A root move's score and principal variation, one per line in MultiPV mode
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<GameMove>,
+}
+
 pub struct SearchOutcome<T> {
     score: i32,
     time: u128,
     pv: Vec<GameMove>,
+    lines: Vec<PvLine>,
     nodes: usize,
     depth: usize,
     t_cuts: u64,
@@ -208,6 +467,10 @@ where
         let t_cuts = search_info.stats.transposition_cutoffs;
         Self {
             score,
+            lines: vec![PvLine {
+                score,
+                pv: pv.clone(),
+            }],
             pv,
             nodes,
             time,
@@ -216,8 +479,26 @@ where
             phantom: PhantomData,
         }
     }
+    pub fn score(&self) -> i32 {
+        self.score
+    }
+    pub fn pv(&self) -> &[GameMove] {
+        &self.pv
+    }
+    /// Moves until a forced win, negative for a forced loss, see [`mate_moves`]
+    pub fn mate(&self) -> Option<i32> {
+        mate_moves(self.score)
+    }
+    /// The lines searched in MultiPV mode, best first. Otherwise only the main line.
+    pub fn lines(&self) -> &[PvLine] {
+        &self.lines
+    }
+    fn with_lines(mut self, lines: Vec<PvLine>) -> Self {
+        self.lines = lines;
+        self
+    }
     pub fn best_move(&self) -> Option<String> {
-        self.pv.get(0).map(|m| m.to_ptn::<T>())
+        self.pv.first().map(|m| m.to_ptn::<T>())
     }
 }
 
@@ -229,23 +510,74 @@ where
         let mut pv_string = String::new();
         for m in self.pv.iter() {
             pv_string.push_str(&m.to_ptn::<T>());
-            pv_string.push_str(" ");
+            pv_string.push(' ');
         }
         pv_string.pop();
-        let nps = if self.time > 0 {
-            (self.nodes as u128) / self.time * 1000
-        } else {
-            0
-        };
+        let nps = (self.nodes as u128)
+            .checked_div(self.time)
+            .map_or(0, |per_ms| per_ms * 1000);
         write!(
             f,
-            "score cp {} time {} pv {} nodes {} nps {} depth {} tcut {}",
-            self.score, self.time, pv_string, self.nodes, nps, self.depth, self.t_cuts
+            "score {} time {} pv {} nodes {} nps {} depth {} tcut {}",
+            TeiScore(self.score), self.time, pv_string, self.nodes, nps, self.depth, self.t_cuts
         )
     }
 }
 
 pub fn search<T, E>(board: &mut T, eval: &E, info: &mut SearchInfo) -> Option<SearchOutcome<T>>
+where
+    T: TakBoard,
+    E: Evaluator<Game = T>,
+{
+    let outcome = search_from(board, eval, info, 1);
+    info.stats.tt_ages = info.pv_table.age_histogram(board.ply());
+    outcome
+}
+
+/// Lazy SMP search on `threads` threads.
+///
+/// Each helper thread runs its own iterative deepening on a copy of the board and
+/// shares nothing with the main search except the transposition table, which it
+/// fills with entries the main search can use. So that the threads do not all walk
+/// the same tree in lockstep, helpers start at depths 1 to 3 in turn and break ties
+/// in their move ordering with a pseudo random offset seeded by their index. The
+/// outcome is always that of the main search, and the helpers are stopped when it
+/// finishes.
+pub fn search_parallel<T, E>(
+    board: &mut T,
+    eval: &E,
+    info: &mut SearchInfo,
+    threads: usize,
+) -> Option<SearchOutcome<T>>
+where
+    T: TakBoard + Clone + Send,
+    E: Evaluator<Game = T> + Clone + Send,
+{
+    if threads <= 1 {
+        return search(board, eval, info);
+    }
+    let stop_signal = Arc::new(AtomicBool::new(false));
+    std::thread::scope(|scope| {
+        for idx in 1..threads {
+            let mut helper_board = board.clone();
+            let helper_eval = eval.clone();
+            let mut helper_info = info.helper(idx, stop_signal.clone());
+            scope.spawn(move || {
+                search_from(&mut helper_board, &helper_eval, &mut helper_info, 1 + idx % 3);
+            });
+        }
+        let outcome = search(board, eval, info);
+        stop_signal.store(true, Ordering::Relaxed);
+        outcome
+    })
+}
+
+fn search_from<T, E>(
+    board: &mut T,
+    eval: &E,
+    info: &mut SearchInfo,
+    start_depth: usize,
+) -> Option<SearchOutcome<T>>
 where
     T: TakBoard,
     E: Evaluator<Game = T>,
@@ -253,13 +585,18 @@ where
     let mut outcome = None;
     let mut node_counts = vec![1];
     info.set_start_ply(board.ply());
+    if info.hist_moves.board_size() != T::SIZE {
+        info.hist_moves = HistoryMoves::new(T::SIZE);
+    }
     let mut alpha = -1_000_000;
     let mut beta = 1_000_000;
-    for depth in 1..=info.max_depth {
+    let mut best_move = None;
+    let mut stable_depths = 0;
+    for depth in start_depth..=info.max_depth {
         // Abort if we are unlikely to finish the search at this depth
-        if info.estimate_time && depth >= 6 {
+        if info.estimate_time && !info.pondering && depth >= 6 {
             let mut est_branch = node_counts[depth - 2] as f64 / node_counts[depth - 3] as f64;
-            if est_branch < 3.0 || est_branch > 100.0 {
+            if !(3.0..=100.0).contains(&est_branch) {
                 // Transposition hits causing instability, just guess
                 if depth % 2 == 0 {
                     // Even nodes are cheaper, see even-odd effect
@@ -271,11 +608,12 @@ where
             let est_nodes = node_counts[depth - 1] as f64 * est_branch;
             let elapsed = info.start_time.elapsed().as_secs_f64();
             let nps = node_counts.iter().copied().sum::<usize>() as f64 / elapsed;
-            let remaining = info.max_time as f64 - elapsed;
+            let remaining = info.hard_time as f64 / 1000.0 - elapsed;
             if est_nodes / nps > remaining {
                 break;
             }
         }
+        info.root_best = None;
         let mut best_score = alpha_beta(
             board,
             eval,
@@ -295,37 +633,122 @@ where
             beta = best_score + ASPIRATION_WINDOW;
         }
         node_counts.push(info.nodes);
-        let pv_moves = info.full_pv(board);
+        let pv_moves = match info.root_best {
+            Some(m) => info.pv_from(board, m),
+            None => info.full_pv(board),
+        };
         // If we had an incomplete depth search, use the previous depth's vals
         if info.stopped {
-            print!(
-                "Aborted Depth: {} Score: {} Nodes: {} PV: ",
-                depth, best_score, info.nodes
-            );
+            if !info.quiet {
+                println!(
+                    "info string Aborted depth {} score {} nodes {}",
+                    depth, best_score, info.nodes
+                );
+            }
+            // Stopped before any depth finished, so the partial result is all we have
+            if outcome.is_none() && !pv_moves.is_empty() {
+                outcome = Some(SearchOutcome::new(best_score, pv_moves, depth, info));
+            }
             break;
         }
-        print!(
-            "info depth {} score cp {} nodes {} hashfull {} pv ",
-            depth, best_score, info.nodes, info.pv_table.occupancy()
+        let lines = multi_pv_lines(board, eval, info, depth, best_score, &pv_moves);
+        outcome = Some(
+            SearchOutcome::new(best_score, pv_moves.clone(), depth, info).with_lines(lines.clone()),
         );
-        outcome = Some(SearchOutcome::new(
-            best_score,
-            pv_moves.clone(),
-            depth,
-            info,
-        ));
-        for ptn in pv_moves.iter().map(|m| m.to_ptn::<T>()) {
-            print!("{} ", ptn);
+        if !info.quiet {
+            for (k, line) in lines.iter().enumerate() {
+                print!("info depth {} ", depth);
+                if info.multi_pv > 1 {
+                    print!("multipv {} ", k + 1);
+                }
+                print!(
+                    "score {} nodes {} hashfull {} pv ",
+                    TeiScore(line.score), info.nodes, info.pv_table.occupancy()
+                );
+                for ptn in line.pv.iter().map(|m| m.to_ptn::<T>()) {
+                    print!("{} ", ptn);
+                }
+                println!();
+            }
+        }
+        // The extra lines were cut short, but the main line finished this depth
+        if info.stopped {
+            break;
         }
-        println!("");
         // Stop wasting time
-        if best_score > WIN_SCORE - 10 || best_score < LOSE_SCORE + 10 {
-            return Some(SearchOutcome::new(best_score, pv_moves, depth, info));
+        if !(LOSE_SCORE + 10..=WIN_SCORE - 10).contains(&best_score) {
+            return outcome;
+        }
+        if pv_moves.first() == best_move.as_ref() {
+            stable_depths += 1;
+        } else {
+            best_move = pv_moves.first().copied();
+            stable_depths = 0;
+        }
+        if info.estimate_time && !info.pondering {
+            let soft_time = if stable_depths >= STABLE_DEPTHS {
+                info.soft_time / 2
+            } else {
+                info.soft_time
+            };
+            if info.elapsed_ms() >= soft_time {
+                break;
+            }
         }
     }
     outcome
 }
 
+/// Searches the alternatives to the main line for MultiPV, each with the root moves
+/// of the better lines excluded. Lines that the stop interrupted are left out.
+fn multi_pv_lines<T, E>(
+    board: &mut T,
+    eval: &E,
+    info: &mut SearchInfo,
+    depth: usize,
+    best_score: i32,
+    pv_moves: &[GameMove],
+) -> Vec<PvLine>
+where
+    T: TakBoard,
+    E: Evaluator<Game = T>,
+{
+    let mut lines = vec![PvLine {
+        score: best_score,
+        pv: pv_moves.to_vec(),
+    }];
+    if info.multi_pv <= 1 || pv_moves.is_empty() {
+        return lines;
+    }
+    info.excluded_root = vec![pv_moves[0]];
+    while lines.len() < info.multi_pv {
+        info.root_best = None;
+        let score = alpha_beta(
+            board,
+            eval,
+            info,
+            SearchData::new(-1_000_000, 1_000_000, depth, true, None, 0, TakHistory(0), true, true),
+        );
+        // Out of time, or every root move already has a line
+        let m = match info.root_best {
+            Some(m) if !info.stopped => m,
+            _ => break,
+        };
+        lines.push(PvLine {
+            score,
+            pv: info.pv_from(board, m),
+        });
+        info.excluded_root.push(m);
+    }
+    info.excluded_root.clear();
+    // The root entry now holds the worst line, so put the best back for the next depth
+    info.store_move(
+        board,
+        HashEntry::new(board.hash(), pv_moves[0], ScoreCutoff::Exact(best_score), depth, board.ply()),
+    );
+    lines
+}
+
 #[derive(Clone, Copy)]
 struct TakHistory(u32);
 
@@ -337,7 +760,7 @@ impl TakHistory {
         if depth < 2 {
             return false;
         }
-        let mask = (1 << depth) | (1 << depth - 2);
+        let mask = (1 << depth) | (1 << (depth - 2));
         (self.0 & mask) == mask
     }
 }
@@ -355,6 +778,8 @@ struct SearchData {
 }
 
 impl SearchData {
+    // Takes every field in order, like a struct literal with shorter call sites
+    #[allow(clippy::too_many_arguments)]
     fn new(
         alpha: i32,
         beta: i32,
@@ -397,7 +822,7 @@ where
         is_root,
     } = data;
     info.nodes += 1;
-    const FREQ: usize = (1 << 16) - 1; // Per 65k nodes
+    const FREQ: usize = (1 << 10) - 1; // Per 1k nodes, often enough for millisecond limits
     if (info.nodes & FREQ) == FREQ {
         info.check_stop();
     }
@@ -417,6 +842,13 @@ where
         Some(GameResult::Draw) => return 0,
         None => {}
     }
+    // The root still needs a move, so it is always searched
+    if !is_root {
+        if let Some(outcome) = info.tablebase.as_ref().and_then(|tb| tb.probe(board)) {
+            info.stats.tablebase_hits += 1;
+            return outcome.score(board.ply() as i32 - info.start_ply as i32);
+        }
+    }
     // let mut road_move = None;
     if depth == 0 {
         // let critical = board.bits().road_pieces(board.side_to_move());
@@ -446,14 +878,12 @@ where
         // road_check.clear();
     }
 
-    let mut pv_entry: Option<HashEntry> = None;
-    let pv_entry_foreign = info.lookup_move(board);
-
-    if let Some(entry) = pv_entry_foreign {
-        pv_entry = Some(entry.clone()); // save for move lookup
-    }
+    let mut pv_entry: Option<HashEntry> = info.lookup_move(board); // save for move lookup
 
-    if let Some(entry) = pv_entry {
+    // A restricted root must not be cut off by an entry for a move outside the list
+    let restricted_root =
+        is_root && !(info.root_moves.is_empty() && info.excluded_root.is_empty());
+    if let Some(entry) = pv_entry.filter(|_| !restricted_root) {
         if entry.depth() as usize >= depth {
             match entry.score() {
                 ScoreCutoff::Alpha(score) => {
@@ -477,20 +907,40 @@ where
     }
     if NULL_REDUCTION_ENABLED
     && (!data.is_pv || NULL_REDUCE_PV)
-    && null_move && depth >= 1 + NULL_REDUCTION {
+    && null_move && depth > NULL_REDUCTION {
         // && road_move.is_none() {
 
         board.null_move();
         // Check if we are in Tak
-        let one_depth_score = -1
-            * alpha_beta(
+        let one_depth_score = -alpha_beta(
+            board,
+            evaluator,
+            info,
+            SearchData::new(
+                -1_000_000,
+                1_000_000,
+                1,
+                false,
+                None,
+                extensions,
+                tak_history,
+                false,
+                false,
+            ),
+        );
+        if one_depth_score <= LOSE_SCORE + 100 {
+            tak_history = tak_history.add(info.ply_depth(board));
+            board.rev_null_move();
+        } else {
+            // Check if our position is so good that passing still gives opp a bad pos
+            let score = -alpha_beta(
                 board,
                 evaluator,
                 info,
                 SearchData::new(
-                    -1_000_000,
-                    1_000_000,
-                    1,
+                    -beta,
+                    -beta + 1,
+                    depth - 1 - NULL_REDUCTION,
                     false,
                     None,
                     extensions,
@@ -499,28 +949,6 @@ where
                     false,
                 ),
             );
-        if one_depth_score <= LOSE_SCORE + 100 {
-            tak_history = tak_history.add(info.ply_depth(board));
-            board.rev_null_move();
-        } else {
-            // Check if our position is so good that passing still gives opp a bad pos
-            let score = -1
-                * alpha_beta(
-                    board,
-                    evaluator,
-                    info,
-                    SearchData::new(
-                        -beta,
-                        -beta + 1,
-                        depth - 1 - NULL_REDUCTION,
-                        false,
-                        None,
-                        extensions,
-                        tak_history,
-                        false,
-                        false,
-                    ),
-                );
             board.rev_null_move();
             // If we beta cutoff from the null move, then we can stop searching
             if score >= beta {
@@ -533,7 +961,7 @@ where
    if IID_ENABLED
    && depth >= IID_MIN_DEPTH
    && (is_pv || IID_NON_PV)
-   && !pv_entry.is_some() {
+   && pv_entry.is_none() {
        let reduction = std::cmp::max(IID_REDUCTION, depth / IID_DIVISION);
        alpha_beta(
            board,
@@ -551,11 +979,7 @@ where
                false,
            ),
        );
-       let pv_entry_foreign_2 = info.lookup_move(board);
-
-       if let Some(entry) = pv_entry_foreign_2 {
-           pv_entry = Some(entry.clone());
-       }
+       pv_entry = info.lookup_move(board);
    }
 
     let mut best_move = None;
@@ -570,26 +994,30 @@ where
     // step 5: score spread moves, generate and score placements in gen_and_score()
     // step 6: search all moves ordered by score.
     let mut stack_moves = Vec::new();
-    let mut moves = SmartMoveBuffer::new();
+    let mut moves = SmartMoveBuffer::new(T::SIZE);
 
     if board.ply() >= 6 && depth > GEN_THOROUGH_ORDER_DEPTH {
         if let Some(mv) = board.can_make_road(&mut stack_moves, None) {
-            let data = &[mv];
-            moves.add_move(mv);
-            moves.score_wins(data);
+            if info.root_allowed(is_root, mv) {
+                let data = &[mv];
+                moves.add_move(mv);
+                moves.score_wins(data);
+            }
         }
     }
 
     let mut has_searched_pv = false;
     if moves.len() == 0 { // if we don't have an immediate win, check TT move first
         if let Some(entry) = pv_entry {
-            if (entry.game_move.is_place_move() && board.legal_move(entry.game_move))
-            || stack_moves.contains(&entry.game_move) // TODO maybe a really fast legal checker is faster
+            if ((entry.game_move.is_place_move() && board.legal_move(entry.game_move))
+            || stack_moves.contains(&entry.game_move)) // TODO maybe a really fast legal checker is faster
+            && info.root_allowed(is_root, entry.game_move)
             {
-                let m = entry.game_move.clone();
+                let m = entry.game_move;
                 let rev_move = board.do_move(m);
+                evaluator.do_move(board, rev_move);
 
-                let score = -1 * alpha_beta(
+                let score = -alpha_beta(
                     board,
                     evaluator,
                     info,
@@ -607,6 +1035,7 @@ where
                 );
 
                 board.reverse_move(rev_move);
+                evaluator.reverse_move(board, rev_move);
                 if info.stopped {
                     return 0;
                 }
@@ -624,11 +1053,17 @@ where
                             //     info.hist_moves.update(depth, m);
                             // }
                         }
+                        if is_root {
+                            info.root_best = Some(m);
+                        }
                         info.store_move(board, HashEntry::new(board.hash(), m, ScoreCutoff::Beta(beta), depth, board.ply()));
                         return beta;
                     }
                     info.stats.add_alpha(0);
                     alpha = score;
+                    if is_root {
+                        info.root_best = Some(m);
+                    }
                     best_move = Some(m);
                     best_score = Some(score);
                 }
@@ -637,7 +1072,10 @@ where
         }
     }
 
-    gen_and_score(depth, board, last_move, &mut stack_moves, &mut moves);
+    gen_and_score(depth, board, last_move, &stack_moves, &mut moves);
+    if info.order_seed != 0 {
+        moves.perturb(info.order_seed ^ board.hash());
+    }
 
     if let Some(entry) = pv_entry {
         if has_searched_pv {
@@ -646,6 +1084,10 @@ where
             moves.score_pv_move(entry.game_move);
         }
     }
+    if restricted_root {
+        let info = &*info;
+        moves.retain(|m| info.root_allowed(true, m));
+    }
 
     for c in 0..moves.len() {
         let count = if has_searched_pv { c + 1 } else { c };
@@ -662,6 +1104,7 @@ where
         // let side = board.side_to_move();
         // let flat_diff = board.flat_diff(side);
         let rev_move = board.do_move(m);
+        evaluator.do_move(board, rev_move);
         let mut next_extensions = extensions;
         // Extend if the pv is to make a "bad capture"
         // if let Some(pv_move) = pv_move {
@@ -714,7 +1157,7 @@ where
 
         // search first move fully!
         if count == 0 {
-            score = -1 * alpha_beta(
+            score = -alpha_beta(
                 board,
                 evaluator,
                 info,
@@ -754,7 +1197,7 @@ where
                 needs_re_search_on_alpha_beta = true;
             }
 
-            score = -1 * alpha_beta(
+            score = -alpha_beta(
                 board,
                 evaluator,
                 info,
@@ -775,7 +1218,7 @@ where
             if needs_re_search_on_alpha
                 && score > alpha
             {
-                score = -1 * alpha_beta(
+                score = -alpha_beta(
                     board,
                     evaluator,
                     info,
@@ -799,7 +1242,7 @@ where
                 && score < beta
                 && (PV_RE_SEARCH_NON_PV || data.is_pv)
             {
-                score = -1 * alpha_beta(
+                score = -alpha_beta(
                     board,
                     evaluator,
                     info,
@@ -819,6 +1262,7 @@ where
         }
 
         board.reverse_move(rev_move);
+        evaluator.reverse_move(board, rev_move);
         if info.stopped {
             return 0;
         }
@@ -838,11 +1282,17 @@ where
                     //     info.hist_moves.update(depth, m);
                     // }
                 }
+                if is_root {
+                    info.root_best = Some(m);
+                }
                 info.store_move(board, HashEntry::new(board.hash(), m, ScoreCutoff::Beta(beta), depth, board.ply()));
                 return beta;
             }
             info.stats.add_alpha(count);
             alpha = score;
+            if is_root {
+                info.root_best = Some(m);
+            }
             best_move = Some(m);
             best_score = Some(score);
         }
@@ -873,7 +1323,7 @@ fn gen_and_score<T>(
     depth: usize,
     board: &mut T,
     last_move: Option<RevGameMove>,
-    stack_moves: &mut Vec<GameMove>,
+    stack_moves: &[GameMove],
     moves: &mut SmartMoveBuffer,
 )
 where
@@ -890,7 +1340,7 @@ where
         }
         let mut check_moves = Vec::new();
         generate_aggressive_place_moves(board, &mut check_moves);
-        let tak_threats = board.get_tak_threats(&mut check_moves, None);
+        let tak_threats = board.get_tak_threats(&check_moves, None);
         moves.score_tak_threats(&tak_threats);
         if board.ply() >= 4 {
             moves.score_stack_moves(board, last_move.filter(|x| x.game_move.is_stack_move()));
@@ -925,11 +1375,6 @@ where
 //     0
 // }
 
-fn road_at_a_glance() {
-    // I
-    todo!();
-}
-
 /// A naive minimax function without pruning used for debugging and benchmarking
 pub fn root_minimax<T, E>(board: &mut T, eval: &E, depth: u16) -> (Option<GameMove>, i32)
 where
@@ -942,7 +1387,7 @@ where
     let mut best_move = None;
     let child_evaluations = moves.into_iter().map(|mv| {
         let reverse_move = board.do_move(mv);
-        let eval = -1 * naive_minimax(board, eval, depth - 1);
+        let eval = -naive_minimax(board, eval, depth - 1);
         board.reverse_move(reverse_move);
         (mv, eval)
     });
@@ -981,7 +1426,7 @@ fn naive_minimax<T: TakBoard, E: Evaluator<Game = T>>(board: &mut T, eval: &E, d
         board.generate_moves(&mut moves);
         let child_evaluations = moves.into_iter().map(|mv| {
             let reverse_move = board.do_move(mv);
-            let eval = -1 * naive_minimax(board, eval, depth - 1);
+            let eval = -naive_minimax(board, eval, depth - 1);
             board.reverse_move(reverse_move);
             eval
         });
@@ -1013,6 +1458,158 @@ mod test {
         search(&mut board, &eval, &mut info);
     }
     #[test]
+    fn table_stats() {
+        let tps = "2,1,1,1,1,2S/1,12,1,x,1C,11112/x,2,2,212,2C,11121/2,21122,x2,1,x/x3,1,1,x/x2,2,21,x,112S 1 34";
+        let mut board = Board6::try_from_tps(tps).unwrap();
+        let mut info = SearchInfo::new(4, 50000).quiet(true);
+        search(&mut board, &Evaluator6 {}, &mut info);
+        let stats = &info.stats;
+        assert!(stats.tt_probes > 0 && stats.tt_stores > 0);
+        assert!(stats.tt_hits + stats.tt_collisions <= stats.tt_probes);
+        assert!(stats.tt_overwrites <= stats.tt_stores);
+        let sampled: usize = stats.tt_ages.iter().sum();
+        assert!(sampled > 0 && info.pv_table.occupancy() > 0);
+    }
+
+    #[test]
+    fn millisecond_time_limit() {
+        let mut board = Board6::try_from_tps(
+            "x3,2,1,1/x3,21,1,21/x,2,121S,2,2,11/1,1,1,2,2,21/x2,2221C,12C,2,2/2,2,21,x,2,x 2 25",
+        )
+        .unwrap();
+        let mut info = SearchInfo::new(30, 50000).time_limits(50, 150).quiet(true);
+        let start = Instant::now();
+        let outcome = search(&mut board, &Evaluator6 {}, &mut info);
+        assert!(outcome.is_some());
+        assert!(start.elapsed().as_millis() < 1000);
+    }
+
+    #[test]
+    fn stop_from_input_stream() {
+        let mut board = Board6::try_from_tps(
+            "x3,2,1,1/x3,21,1,21/x,2,121S,2,2,11/1,1,1,2,2,21/x2,2221C,12C,2,2/2,2,21,x,2,x 2 25",
+        )
+        .unwrap();
+        let (sender, r) = crossbeam_channel::unbounded();
+        sender.send(TeiCommand::Stop).unwrap();
+        sender.send(TeiCommand::Eval).unwrap();
+        let mut info = SearchInfo::new(30, 50000).input_stream(r).quiet(true);
+        let start = Instant::now();
+        let outcome = search(&mut board, &Evaluator6 {}, &mut info);
+        assert!(outcome.is_some());
+        assert!(start.elapsed().as_millis() < 1000);
+        assert!(info.interrupted());
+        let pending = info.take_pending();
+        assert!(matches!(pending[..], [TeiCommand::Eval]));
+    }
+
+    #[test]
+    fn options_do_not_stop() {
+        let (sender, r) = crossbeam_channel::unbounded();
+        let mut info = SearchInfo::new(30, 0).input_stream(r).quiet(true);
+        sender.send(TeiCommand::Threads(2)).unwrap();
+        sender.send(TeiCommand::MaxDepth(10)).unwrap();
+        sender.send(TeiCommand::MultiPv(3)).unwrap();
+        sender.send(TeiCommand::ResizeHash(64)).unwrap();
+        sender.send(TeiCommand::Ponder(true)).unwrap();
+        info.check_stop();
+        assert!(!info.stopped);
+        assert!(!info.interrupted());
+        assert_eq!(info.take_pending().len(), 5);
+        sender.send(TeiCommand::Position("position startpos".to_string())).unwrap();
+        info.check_stop();
+        assert!(info.stopped);
+        assert!(info.interrupted());
+        assert!(matches!(info.take_pending()[..], [TeiCommand::Position(_)]));
+    }
+
+    #[test]
+    fn ponder_until_ponderhit() {
+        let mut board = Board6::try_from_tps(
+            "x3,2,1,1/x3,21,1,21/x,2,121S,2,2,11/1,1,1,2,2,21/x2,2221C,12C,2,2/2,2,21,x,2,x 2 25",
+        )
+        .unwrap();
+        let (sender, r) = crossbeam_channel::unbounded();
+        let mut info = SearchInfo::new(30, 50000)
+            .time_limits(50, 100)
+            .input_stream(r)
+            .ponder(true)
+            .quiet(true);
+        let hit = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            sender.send(TeiCommand::PonderHit).unwrap();
+        });
+        let start = Instant::now();
+        let outcome = search(&mut board, &Evaluator6 {}, &mut info);
+        hit.join().unwrap();
+        assert!(outcome.is_some());
+        assert!(start.elapsed().as_millis() >= 300);
+        assert!(start.elapsed().as_millis() < 1500);
+        assert!(!info.pondering());
+        assert!(!info.interrupted());
+    }
+
+    #[test]
+    fn multi_pv_lines() {
+        let mut board = Board6::try_from_tps(
+            "x3,2,1,1/x3,21,1,21/x,2,121S,2,2,11/1,1,1,2,2,21/x2,2221C,12C,2,2/2,2,21,x,2,x 2 25",
+        )
+        .unwrap();
+        let mut info = SearchInfo::new(4, 50000).multi_pv(3).quiet(true);
+        let outcome = search(&mut board, &Evaluator6 {}, &mut info).unwrap();
+        let lines = outcome.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].pv, outcome.pv());
+        assert!(lines[0].score >= lines[1].score);
+        assert!(lines[1].pv[0] != lines[0].pv[0]);
+        assert!(lines[2].pv[0] != lines[0].pv[0] && lines[2].pv[0] != lines[1].pv[0]);
+    }
+
+    #[test]
+    fn mate_scores() {
+        assert_eq!(mate_moves(WIN_SCORE - 1), Some(1));
+        assert_eq!(mate_moves(WIN_SCORE - 3), Some(2));
+        assert_eq!(mate_moves(LOSE_SCORE + 2), Some(-1));
+        assert_eq!(mate_moves(350), None);
+        assert_eq!(TeiScore(LOSE_SCORE + 4).to_string(), "mate -2");
+        // A win 3 plies from the root is 1 ply away from a position 2 plies deep
+        assert_eq!(score_to_tt(WIN_SCORE - 3, 2), WIN_SCORE - 1);
+        assert_eq!(score_from_tt(WIN_SCORE - 1, 4), WIN_SCORE - 5);
+        assert_eq!(score_from_tt(score_to_tt(LOSE_SCORE + 6, 3), 3), LOSE_SCORE + 6);
+        assert_eq!(score_to_tt(120, 5), 120);
+
+        let mut board = Board6::try_from_tps("x6/x6/x6/1,1,1,1,1,x/2,2,2,2,x2/x6 1 6").unwrap();
+        let mut info = SearchInfo::new(4, 50000).quiet(true);
+        let outcome = search(&mut board, &Evaluator6 {}, &mut info).unwrap();
+        assert_eq!(outcome.mate(), Some(1));
+        assert!(outcome.to_string().starts_with("score mate 1 "));
+    }
+
+    #[test]
+    fn restricted_root_moves() {
+        let tps = "2,1,1,1,1,2S/1,12,1,x,1C,11112/x,2,2,212,2C,11121/2,21122,x2,1,x/x3,1,1,x/x2,2,21,x,112S 1 34";
+        let mut board = Board6::try_from_tps(tps).unwrap();
+        let only = GameMove::try_from_ptn("a1", &board).unwrap();
+        let mut info = SearchInfo::new(3, 50000).root_moves(vec![only]).quiet(true);
+        let outcome = search(&mut board, &Evaluator6 {}, &mut info).unwrap();
+        assert_eq!(outcome.best_move().unwrap(), "a1");
+    }
+
+    #[test]
+    fn lazy_smp() {
+        let tps = "2,1,1,1,1,2S/1,12,1,x,1C,11112/x,2,2,212,2C,11121/2,21122,x2,1,x/x3,1,1,x/x2,2,21,x,112S 1 34";
+        let mut board = Board6::try_from_tps(tps).unwrap();
+        let eval = Evaluator6 {};
+        let mut single = SearchInfo::new(4, 50000).quiet(true);
+        let expected = search(&mut board.clone(), &eval, &mut single).unwrap();
+        let mut info = SearchInfo::new(4, 50000).quiet(true);
+        let outcome = search_parallel(&mut board, &eval, &mut info, 4).unwrap();
+        assert!(!outcome.pv().is_empty());
+        assert!(board.legal_move(outcome.pv()[0]));
+        // Helpers fill the table in a different order, which may shift the score a little
+        assert!((outcome.score() - expected.score()).abs() <= 100);
+    }
+    #[test]
     fn unk_puzzle() {
         let tps = "x2,1,21,2,2/1,2,21,1,21,2/1S,2,2,2C,2,2/21S,1,121C,x,1,12/2,2,121,1,1,1/2,2,x3,22S 1 27";
         let mut board = Board6::try_from_tps(tps).unwrap();
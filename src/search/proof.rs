@@ -57,7 +57,7 @@ fn compute_bounds(children: &[Child]) -> Bounds {
         out.delta = out.delta.saturating_add(ch.bounds.phi);
     }
     out.delta = min(out.delta, INFINITY);
-    return out;
+    out
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -189,7 +189,7 @@ where
             .map(|(m, _)| m.to_ptn::<T>())
             .collect();
         let mut tree = Tree::root(Solved::Root(line));
-        if self.view_hist.len() % 2 == 0 {
+        if self.view_hist.len().is_multiple_of(2) {
             self.recurse_attack(&mut tree);
         } else {
             self.recurse_defend(&mut tree);
@@ -277,12 +277,12 @@ where
         use colorful::Color;
         use colorful::Colorful;
         let s = match self {
-            Solved::Proved(m) => format!("{}", m.to_ptn::<T>()),
-            Solved::Disproved(m) => format!("{}", m.to_ptn::<T>()),
+            Solved::Proved(m) => m.to_ptn::<T>().to_string(),
+            Solved::Disproved(m) => m.to_ptn::<T>().to_string(),
             Solved::Unknown(m) => return write!(f, "{}", m.to_ptn::<T>()),
             Solved::AttackerRoad(m) => format!("{}''", m.to_ptn::<T>()),
             Solved::DefenderRoad(m) => format!("{}''", m.to_ptn::<T>()),
-            Solved::AttackerNoMoves(_) => format!("∅"),
+            Solved::AttackerNoMoves(_) => "∅".to_string(),
             Solved::Root(vec) => {
                 let move_str = vec.join("/");
                 return write!(f, "ROOT({})", move_str);
@@ -481,7 +481,7 @@ where
         // Todo optimization: only check if there is only one flat threat
         let placement =
             crate::board::find_placement_road(enemy, enemy_road_pieces, board.bits().empty());
-        assert!(moves.len() > 0); // All stack moves should already be generated
+        assert!(!moves.is_empty()); // All stack moves should already be generated
         generate_all_place_moves(board, &mut moves);
         // generate_all_moves(&board, &mut moves); // In practice generating them twice helps??
         let mut moves: Vec<GameMove> = moves
@@ -542,7 +542,7 @@ where
         };
         let bounds = self.bounds_table.entry(hash).or_insert(default_bounds);
 
-        let child = Child::new(bounds.clone(), game_move, hash);
+        let child = Child::new(*bounds, game_move, hash);
         self.board.reverse_move(rev);
         if attacker && self.zobrist_hist.contains(&hash) {
             return None;
@@ -0,0 +1,149 @@
+use crate::board::TakBoard;
+use crate::eval::{Evaluator, Weights};
+use crate::search::{search, SearchInfo};
+use crate::transposition_table::HashTable;
+use crate::{generate_all_moves, Color, GameResult};
+use anyhow::{anyhow, Result};
+use rand_core::RngCore;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Limits and sources of variety for engine vs engine games
+#[derive(Clone, Debug)]
+pub struct SelfPlayConfig {
+    pub max_depth: usize,
+    pub max_nodes: Option<usize>,
+    /// Number of uniformly random plies played before the engines take over
+    pub random_plies: usize,
+    /// Perturbs the location weights of both sides at the start of every game
+    pub add_noise: bool,
+    pub komi: u8,
+    /// Games still running after this many plies are scored as draws
+    pub max_plies: usize,
+    /// Transposition table size in megabytes
+    pub hash_mb: usize,
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 6,
+            max_nodes: None,
+            random_plies: 4,
+            add_noise: false,
+            komi: 0,
+            max_plies: 300,
+            hash_mb: 16,
+        }
+    }
+}
+
+/// Every position searched in a game along with its score from white's perspective
+pub struct GameRecord {
+    positions: Vec<(String, i32)>,
+    result: GameResult,
+}
+
+impl GameRecord {
+    pub fn result(&self) -> GameResult {
+        self.result
+    }
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+    /// Writes one `<tps>;<result>;<score>` line per position, the format read by
+    /// [`crate::tune::read_dataset`]
+    pub fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let result = match self.result {
+            GameResult::WhiteWin => "1-0",
+            GameResult::BlackWin => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        };
+        for (tps, score) in self.positions.iter() {
+            writeln!(writer, "{};{};{}", tps, result, score)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays a single game of the engine against itself
+pub fn play_game<T: TakBoard, R: RngCore>(config: &SelfPlayConfig, rng: &mut R) -> Result<GameRecord> {
+    let mut board = T::start_position().with_komi(config.komi);
+    let mut moves = Vec::new();
+    for _ in 0..config.random_plies {
+        if board.game_result().is_some() {
+            break;
+        }
+        moves.clear();
+        generate_all_moves(&board, &mut moves);
+        let idx = rng.next_u32() as usize % moves.len();
+        board.do_move(moves[idx]);
+    }
+    let mut evals = [Weights::<T>::default(), Weights::<T>::default()];
+    if config.add_noise {
+        for eval in evals.iter_mut() {
+            eval.add_noise();
+        }
+    }
+    let table = Arc::new(HashTable::with_mb(config.hash_mb));
+    let mut positions = Vec::new();
+    let result = loop {
+        if let Some(result) = board.game_result() {
+            break result;
+        }
+        if board.ply() >= config.max_plies {
+            break GameResult::Draw;
+        }
+        let eval = match board.side_to_move() {
+            Color::White => &evals[0],
+            Color::Black => &evals[1],
+        };
+        let mut info = SearchInfo::new(config.max_depth, 0)
+            .shared_table(table.clone())
+            .quiet(true);
+        if let Some(nodes) = config.max_nodes {
+            info = info.max_nodes(nodes);
+        }
+        let outcome =
+            search(&mut board, eval, &mut info).ok_or_else(|| anyhow!("Search failed"))?;
+        let score = match board.side_to_move() {
+            Color::White => outcome.score(),
+            Color::Black => -outcome.score(),
+        };
+        positions.push((board.to_tps(), score));
+        let best = outcome
+            .pv()
+            .first()
+            .ok_or_else(|| anyhow!("Search did not return a move"))?;
+        board.do_move(*best);
+    };
+    Ok(GameRecord { positions, result })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::Board5;
+    use rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+    #[test]
+    fn short_selfplay_game() {
+        let config = SelfPlayConfig {
+            max_depth: 2,
+            max_plies: 20,
+            ..SelfPlayConfig::default()
+        };
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(0);
+        let record = play_game::<Board5, _>(&config, &mut rng).unwrap();
+        assert!(!record.is_empty());
+        let mut out = Vec::new();
+        record.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        for line in text.lines() {
+            assert!(crate::tune::TunePosition::<Board5>::parse(line).is_ok());
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use crate::board::TakBoard;
+use crate::{generate_all_moves, GameResult};
+use anyhow::{bail, ensure, Result};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"TZTB";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 17;
+/// Bytes per position, a hash and a packed outcome
+const ENTRY_LEN: u64 = 10;
+/// Positions are indexed with u32 while solving, so no tablebase holds more
+const MAX_ENTRIES: u64 = u32::MAX as u64;
+/// Entries allocated up front when reading, the rest as they are read
+const READ_CHUNK: usize = 1 << 16;
+
+const WIN_FLAG: u16 = 0x4000;
+const LOSS_FLAG: u16 = 0x8000;
+const DIST_MASK: u16 = 0x3FFF;
+
+/// The game theoretic value of a position from the perspective of the side to move,
+/// along with the number of plies until the game ends under optimal play. The winning
+/// side takes the fastest win, the losing side delays for as long as possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win(u16),
+    Loss(u16),
+    Draw,
+}
+
+impl Outcome {
+    fn pack(self) -> u16 {
+        match self {
+            Outcome::Win(d) => WIN_FLAG | d,
+            Outcome::Loss(d) => LOSS_FLAG | d,
+            Outcome::Draw => 0,
+        }
+    }
+    fn unpack(val: u16) -> Result<Self> {
+        let dist = val & DIST_MASK;
+        match val & !DIST_MASK {
+            WIN_FLAG => Ok(Outcome::Win(dist)),
+            LOSS_FLAG => Ok(Outcome::Loss(dist)),
+            0 if dist == 0 => Ok(Outcome::Draw),
+            _ => bail!("Invalid tablebase entry {:#x}", val),
+        }
+    }
+    /// The search score for this outcome, where ply_depth is the distance of the probed
+    /// position from the search root. Matches the scores alpha_beta gives terminal nodes.
+    pub fn score(self, ply_depth: i32) -> i32 {
+        use crate::eval::{LOSE_SCORE, WIN_SCORE};
+        match self {
+            Outcome::Win(d) => WIN_SCORE - ply_depth - d as i32,
+            Outcome::Loss(d) => LOSE_SCORE + ply_depth + d as i32,
+            Outcome::Draw => 0,
+        }
+    }
+}
+
+/// A solved set of positions on a small board, keyed by zobrist hash.
+///
+/// Every position reachable from the root is labeled by exhaustive forward enumeration
+/// followed by retrograde analysis. Positions which can cycle forever without either
+/// side being able to force a result are labeled as draws.
+pub struct Tablebase {
+    size: usize,
+    komi: u8,
+    complete: bool,
+    hashes: Vec<u64>,
+    values: Vec<u16>,
+}
+
+impl Tablebase {
+    /// Solves every position reachable from root within horizon plies. If the horizon is
+    /// never reached the solve is exhaustive and positions where neither side can force
+    /// a result are stored as draws. Otherwise only results which are fully proven inside
+    /// the horizon are kept, i.e. a position d plies from the root with a distance to
+    /// result of r is stored only if d + r <= horizon. Fails if more than max_positions
+    /// distinct positions are found, so that an overly ambitious root cannot exhaust memory.
+    pub fn generate<T: TakBoard + Clone>(
+        root: &T,
+        horizon: usize,
+        max_positions: usize,
+    ) -> Result<Self> {
+        let graph = PositionGraph::build(root, horizon, max_positions)?;
+        let complete = graph.complete();
+        let values = graph.solve()?;
+        let mut entries: Vec<_> = graph
+            .hashes
+            .iter()
+            .zip(graph.depths.iter())
+            .zip(values)
+            .filter_map(|((&hash, &depth), value)| {
+                let value = match value {
+                    Some(Outcome::Win(r)) | Some(Outcome::Loss(r))
+                        if complete || (depth + r) as usize <= horizon =>
+                    {
+                        value.unwrap()
+                    }
+                    Some(Outcome::Draw) => Outcome::Draw,
+                    None if complete => Outcome::Draw,
+                    _ => return None,
+                };
+                Some((hash, value.pack()))
+            })
+            .collect();
+        entries.sort_unstable_by_key(|&(hash, _)| hash);
+        let (hashes, values) = entries.into_iter().unzip();
+        Ok(Self {
+            size: T::SIZE,
+            komi: root.komi(),
+            complete,
+            hashes,
+            values,
+        })
+    }
+    pub fn probe<T: TakBoard>(&self, board: &T) -> Option<Outcome> {
+        if T::SIZE != self.size || board.komi() != self.komi {
+            return None;
+        }
+        let idx = self.hashes.binary_search(&board.hash()).ok()?;
+        Outcome::unpack(self.values[idx]).ok()
+    }
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn komi(&self) -> u8 {
+        self.komi
+    }
+    /// Whether every position reachable from the root was solved
+    pub fn complete(&self) -> bool {
+        self.complete
+    }
+    /// Counts of (wins, losses, draws) for the side to move
+    pub fn summary(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for &val in self.values.iter() {
+            match Outcome::unpack(val) {
+                Ok(Outcome::Win(_)) => counts.0 += 1,
+                Ok(Outcome::Loss(_)) => counts.1 += 1,
+                _ => counts.2 += 1,
+            }
+        }
+        counts
+    }
+    /// Writes the tablebase as a header followed by the sorted hashes and packed values,
+    /// all little endian
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&[self.size as u8, self.komi, self.complete as u8])?;
+        writer.write_all(&(self.hashes.len() as u64).to_le_bytes())?;
+        for hash in self.hashes.iter() {
+            writer.write_all(&hash.to_le_bytes())?;
+        }
+        for val in self.values.iter() {
+            writer.write_all(&val.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+    /// Reads a tablebase written by [`Tablebase::write`]. The entry count in the header is
+    /// not trusted for allocation, so a truncated file fails once its data runs out
+    pub fn read<R: Read>(reader: R) -> Result<Self> {
+        Self::read_checked(reader, None)
+    }
+    // reads a tablebase, checking the entry count in the header against MAX_ENTRIES and,
+    // when it is known, the length of the file
+    fn read_checked<R: Read>(mut reader: R, file_len: Option<u64>) -> Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        ensure!(&header[0..4] == MAGIC, "Not a tablebase file");
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        ensure!(version == VERSION, "Unsupported tablebase version {}", version);
+        let size = header[6] as usize;
+        let komi = header[7];
+        let complete = header[8] != 0;
+        let mut count = [0; 8];
+        count.copy_from_slice(&header[9..17]);
+        let count = u64::from_le_bytes(count);
+        ensure!(
+            count <= MAX_ENTRIES,
+            "Tablebase has {} entries, more than the maximum of {}",
+            count,
+            MAX_ENTRIES
+        );
+        if let Some(len) = file_len {
+            let expected = HEADER_LEN as u64 + count * ENTRY_LEN;
+            ensure!(
+                len == expected,
+                "Tablebase file is {} bytes, expected {} for {} entries",
+                len,
+                expected,
+                count
+            );
+        }
+        let count = count as usize;
+        // Grow as the data arrives rather than trusting the header
+        let capacity = count.min(READ_CHUNK);
+        let mut hashes = Vec::with_capacity(capacity);
+        let mut buf = [0; 8];
+        for _ in 0..count {
+            reader.read_exact(&mut buf)?;
+            hashes.push(u64::from_le_bytes(buf));
+        }
+        let mut values = Vec::with_capacity(capacity);
+        let mut buf = [0; 2];
+        for _ in 0..count {
+            reader.read_exact(&mut buf)?;
+            let val = u16::from_le_bytes(buf);
+            Outcome::unpack(val)?;
+            values.push(val);
+        }
+        ensure!(
+            hashes.windows(2).all(|w| w[0] < w[1]),
+            "Tablebase hashes are not sorted"
+        );
+        Ok(Self {
+            size,
+            komi,
+            complete,
+            hashes,
+            values,
+        })
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.write(std::io::BufWriter::new(file))
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read_checked(std::io::BufReader::new(file), Some(len))
+    }
+}
+
+/// The game graph below a root position, explored breadth first up to a horizon.
+/// Node 0 is the root
+struct PositionGraph {
+    hashes: Vec<u64>,
+    depths: Vec<u16>,
+    children: Vec<Vec<u32>>,
+    expanded: Vec<bool>,
+    terminal: Vec<Option<Outcome>>,
+}
+
+impl PositionGraph {
+    fn build<T: TakBoard + Clone>(root: &T, horizon: usize, max_positions: usize) -> Result<Self> {
+        let mut graph = Self {
+            hashes: Vec::new(),
+            depths: Vec::new(),
+            children: Vec::new(),
+            expanded: Vec::new(),
+            terminal: Vec::new(),
+        };
+        let mut index: HashMap<u64, u32> = HashMap::new();
+        let root_node = graph.add_node(root, 0, &mut index);
+        let mut frontier = Vec::new();
+        if graph.terminal[root_node as usize].is_none() && horizon > 0 {
+            frontier.push((root_node, root.clone()));
+        }
+        let mut moves = Vec::new();
+        // Breadth first, so that every position is labeled with its shortest distance
+        // from the root and is expanded if it lies inside the horizon
+        for depth in 1..=horizon {
+            let mut next_frontier = Vec::new();
+            for (node, mut board) in frontier.into_iter() {
+                graph.expanded[node as usize] = true;
+                moves.clear();
+                generate_all_moves(&board, &mut moves);
+                for m in moves.iter().copied() {
+                    let rev = board.do_move(m);
+                    let child = if let Some(&child) = index.get(&board.hash()) {
+                        child
+                    } else {
+                        ensure!(
+                            graph.hashes.len() < max_positions,
+                            "Exceeded {} positions",
+                            max_positions
+                        );
+                        let child = graph.add_node(&board, depth, &mut index);
+                        if graph.terminal[child as usize].is_none() && depth < horizon {
+                            next_frontier.push((child, board.clone()));
+                        }
+                        child
+                    };
+                    graph.children[node as usize].push(child);
+                    board.reverse_move(rev);
+                }
+            }
+            frontier = next_frontier;
+        }
+        Ok(graph)
+    }
+    /// Whether every non-terminal position was expanded, i.e. the horizon was never hit
+    fn complete(&self) -> bool {
+        self.terminal
+            .iter()
+            .zip(self.expanded.iter())
+            .all(|(t, &e)| t.is_some() || e)
+    }
+    fn add_node<T: TakBoard>(
+        &mut self,
+        board: &T,
+        depth: usize,
+        index: &mut HashMap<u64, u32>,
+    ) -> u32 {
+        let node = self.hashes.len() as u32;
+        let side = board.side_to_move();
+        let terminal = board.game_result().map(|res| match res {
+            GameResult::Draw => Outcome::Draw,
+            GameResult::WhiteWin if side == crate::Color::White => Outcome::Win(0),
+            GameResult::BlackWin if side == crate::Color::Black => Outcome::Win(0),
+            _ => Outcome::Loss(0),
+        });
+        index.insert(board.hash(), node);
+        self.hashes.push(board.hash());
+        self.depths.push(depth as u16);
+        self.children.push(Vec::new());
+        self.expanded.push(false);
+        self.terminal.push(terminal);
+        node
+    }
+    /// Retrograde analysis from the terminal positions. Nodes are resolved in order of
+    /// increasing distance, so the first losing child found gives the fastest win and
+    /// the last winning child found gives the longest loss. Positions past the horizon
+    /// have unknown children, so they can never be labeled a loss.
+    fn solve(&self) -> Result<Vec<Option<Outcome>>> {
+        let count = self.hashes.len();
+        let mut parents = vec![Vec::new(); count];
+        for (parent, children) in self.children.iter().enumerate() {
+            for &child in children.iter() {
+                parents[child as usize].push(parent as u32);
+            }
+        }
+        let mut remaining: Vec<usize> = self
+            .children
+            .iter()
+            .zip(self.expanded.iter())
+            .map(|(c, &e)| if e { c.len() } else { usize::MAX })
+            .collect();
+        let mut values = self.terminal.clone();
+        let mut queue: VecDeque<u32> = (0..count as u32)
+            .filter(|&n| matches!(values[n as usize], Some(Outcome::Win(_) | Outcome::Loss(_))))
+            .collect();
+        while let Some(node) = queue.pop_front() {
+            let outcome = values[node as usize].unwrap();
+            for &parent in parents[node as usize].iter() {
+                let parent = parent as usize;
+                if values[parent].is_some() {
+                    continue;
+                }
+                match outcome {
+                    Outcome::Loss(d) => {
+                        ensure!(d < DIST_MASK, "Distance to result too large");
+                        values[parent] = Some(Outcome::Win(d + 1));
+                        queue.push_back(parent as u32);
+                    }
+                    Outcome::Win(d) => {
+                        remaining[parent] -= 1;
+                        if remaining[parent] == 0 {
+                            ensure!(d < DIST_MASK, "Distance to result too large");
+                            values[parent] = Some(Outcome::Loss(d + 1));
+                            queue.push_back(parent as u32);
+                        }
+                    }
+                    Outcome::Draw => {}
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::Board3;
+    use crate::eval::{Evaluator, WIN_SCORE};
+    use crate::search::{search, SearchInfo};
+    use crate::{GameMove, Position};
+    use std::sync::Arc;
+
+    struct ZeroEval;
+    impl Evaluator for ZeroEval {
+        type Game = Board3;
+        fn evaluate(&self, _game: &Self::Game, _depth: usize) -> i32 {
+            0
+        }
+    }
+
+    #[test]
+    fn forced_flat_loss() {
+        // White must place its last piece, ending the game behind on flats
+        let board = Board3::try_from_tps("1112,x,x/x,11112,x/x,x,112 1 12").unwrap();
+        let tb = Tablebase::generate(&board, 10, 1000).unwrap();
+        assert!(tb.complete());
+        assert_eq!(tb.probe(&board), Some(Outcome::Loss(1)));
+        assert_eq!(tb.len(), 13);
+        assert_eq!(tb.summary(), (12, 1, 0));
+    }
+    #[test]
+    fn horizon_results() {
+        let mut board = Board3::try_from_tps("2,x,1/x,1,x/x,x,2 2 3").unwrap();
+        let tb = Tablebase::generate(&board, 2, 100_000).unwrap();
+        assert_eq!(tb.probe(&board), None);
+        let tb = Tablebase::generate(&board, 4, 100_000).unwrap();
+        assert!(!tb.complete());
+        assert_eq!(tb.probe(&board), Some(Outcome::Win(3)));
+        // The best child is a loss in 2 and no child loses any faster
+        let mut moves: Vec<GameMove> = Vec::new();
+        board.generate_moves(&mut moves);
+        let mut child_outcomes = Vec::new();
+        for m in moves {
+            let rev = board.do_move(m);
+            child_outcomes.push(tb.probe(&board));
+            board.reverse_move(rev);
+        }
+        assert!(child_outcomes.contains(&Some(Outcome::Loss(2))));
+        assert!(!child_outcomes.contains(&Some(Outcome::Loss(0))));
+        assert!(!child_outcomes.contains(&Some(Outcome::Loss(1))));
+    }
+    #[test]
+    fn read_write() {
+        let board = Board3::try_from_tps("2,x,1/x,1,x/x,x,2 2 3").unwrap();
+        let tb = Tablebase::generate(&board, 3, 100_000).unwrap();
+        let mut buf = Vec::new();
+        tb.write(&mut buf).unwrap();
+        let tb2 = Tablebase::read(&buf[..]).unwrap();
+        assert_eq!(tb.len(), tb2.len());
+        assert_eq!((tb2.size(), tb2.komi()), (3, 0));
+        assert_eq!(tb.complete(), tb2.complete());
+        assert_eq!(tb.hashes, tb2.hashes);
+        assert_eq!(tb.values, tb2.values);
+        buf[0] = b'X';
+        assert!(Tablebase::read(&buf[..]).is_err());
+    }
+    #[test]
+    fn read_corrupt() {
+        let board = Board3::try_from_tps("2,x,1/x,1,x/x,x,2 2 3").unwrap();
+        let tb = Tablebase::generate(&board, 3, 100_000).unwrap();
+        let mut buf = Vec::new();
+        tb.write(&mut buf).unwrap();
+        // Truncated data
+        assert!(Tablebase::read(&buf[..buf.len() - 1]).is_err());
+        assert!(Tablebase::read(&buf[..HEADER_LEN]).is_err());
+        // A count beyond the maximum, and one larger than the data that follows
+        let mut bad = buf.clone();
+        bad[9..17].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Tablebase::read(&bad[..]).is_err());
+        bad[9..17].copy_from_slice(&MAX_ENTRIES.to_le_bytes());
+        assert!(Tablebase::read(&bad[..]).is_err());
+        // The file length is checked against the count before reading
+        let len = buf.len() as u64;
+        assert!(Tablebase::read_checked(&bad[..], Some(len)).is_err());
+        assert!(Tablebase::read_checked(&buf[..], Some(len - 1)).is_err());
+        assert_eq!(Tablebase::read_checked(&buf[..], Some(len)).unwrap().len(), tb.len());
+    }
+    #[test]
+    fn search_agrees() {
+        let mut board = Board3::try_from_tps("2,x,1/x,1,x/x,x,2 2 3").unwrap();
+        // Reductions mean the win in 3 is only seen at a higher nominal depth
+        let mut info = SearchInfo::new(6, 10_000);
+        let outcome = search(&mut board, &ZeroEval, &mut info).unwrap();
+        assert_eq!(outcome.score(), WIN_SCORE - 3);
+
+        let tb = Arc::new(Tablebase::generate(&board, 4, 100_000).unwrap());
+        let mut info = SearchInfo::new(1, 10_000).tablebase(tb);
+        let outcome = search(&mut board, &ZeroEval, &mut info).unwrap();
+        assert_eq!(outcome.score(), WIN_SCORE - 3);
+        assert!(info.stats.tablebase_hits > 0);
+    }
+}
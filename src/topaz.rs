@@ -3,14 +3,17 @@ use anyhow::Result;
 use board_game_traits::Position;
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use getopts::Options;
+use std::collections::VecDeque;
 use std::env;
 use std::io::{self, BufRead};
+use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
 use telnet::Event;
-use topaz_tak::board::Board6;
-use topaz_tak::eval::Weights6;
-use topaz_tak::search::{proof::TinueSearch, search, SearchInfo};
+use topaz_tak::board::{Board5, Board6, Board7, Board8};
+use topaz_tak::eval::{Evaluator, NnueEvaluator, Weights, Weights6, WeightsFile};
+use topaz_tak::search::{proof::TinueSearch, search, search_parallel, SearchInfo};
+use topaz_tak::transposition_table::{HashTable, MAX_HASH_MB, MAX_TT_DEPTH};
 use topaz_tak::*;
 
 pub fn main() {
@@ -51,7 +54,7 @@ pub fn main() {
             let mut rest = String::new();
             for s in args[2..].iter() {
                 rest.push_str(s);
-                rest.push_str(" ");
+                rest.push(' ');
             }
             rest.pop();
             let tps = if let Some(tps) = saved_tps(rest.as_str()) {
@@ -67,26 +70,109 @@ pub fn main() {
                 }
             };
             match game {
+                TakGame::Standard3(board) => {
+                    let search = crate::search::proof::TinueSearch::new(*board);
+                    proof_interactive(search).unwrap();
+                }
+                TakGame::Standard4(board) => {
+                    let search = crate::search::proof::TinueSearch::new(*board);
+                    proof_interactive(search).unwrap();
+                }
                 TakGame::Standard5(board) => {
-                    let search = crate::search::proof::TinueSearch::new(board);
+                    let search = crate::search::proof::TinueSearch::new(*board);
                     proof_interactive(search).unwrap();
                 }
                 TakGame::Standard6(board) => {
-                    let search = crate::search::proof::TinueSearch::new(board);
+                    let search = crate::search::proof::TinueSearch::new(*board);
                     proof_interactive(search).unwrap();
                 }
                 TakGame::Standard7(board) => {
-                    let search = crate::search::proof::TinueSearch::new(board);
+                    let search = crate::search::proof::TinueSearch::new(*board);
+                    proof_interactive(search).unwrap();
+                }
+                TakGame::Standard8(board) => {
+                    let search = crate::search::proof::TinueSearch::new(*board);
                     proof_interactive(search).unwrap();
                 }
                 _ => todo!(),
             }
             return;
+        } else if arg1 == "tablebase" {
+            if args.len() < 5 {
+                println!("Usage: topaz tablebase <horizon> <output file> <tps>");
+                return;
+            }
+            let horizon: usize = match args[2].parse() {
+                Ok(h) => h,
+                Err(_) => {
+                    println!("Unable to parse horizon: {}", args[2]);
+                    return;
+                }
+            };
+            let tps = args[4..].join(" ");
+            let res = match TakGame::try_from_tps(&tps) {
+                Ok(TakGame::Standard3(board)) => build_tablebase(&*board, horizon, &args[3]),
+                Ok(TakGame::Standard4(board)) => build_tablebase(&*board, horizon, &args[3]),
+                Ok(_) => Err(anyhow::anyhow!("Tablebases only support 3x3 and 4x4 boards")),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                println!("Unable to build tablebase: {}", e);
+            }
+            return;
+        } else if arg1 == "tune" {
+            if args.len() < 4 {
+                println!("Usage: topaz tune <dataset> <weights file> [iterations]");
+                return;
+            }
+            let iterations = match args.get(4).map(|s| s.parse()) {
+                Some(Ok(i)) => i,
+                Some(Err(_)) => {
+                    println!("Unable to parse iterations: {}", args[4]);
+                    return;
+                }
+                None => 100,
+            };
+            let res = match dataset_size(&args[2]) {
+                Ok(5) => tune_weights::<Board5>(&args[2], &args[3], iterations),
+                Ok(6) => tune_weights::<Board6>(&args[2], &args[3], iterations),
+                Ok(7) => tune_weights::<Board7>(&args[2], &args[3], iterations),
+                Ok(8) => tune_weights::<Board8>(&args[2], &args[3], iterations),
+                Ok(size) => Err(anyhow::anyhow!("Unsupported board size: {}", size)),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                println!("Unable to tune weights: {}", e);
+            }
+            return;
+        } else if arg1 == "eval" {
+            let tps = args[2..].join(" ");
+            let tps = saved_tps(&tps).unwrap_or(&tps);
+            match TakGame::try_from_tps(tps) {
+                Ok(TakGame::Standard5(board)) => print!("{}", trace_eval(&*board)),
+                Ok(TakGame::Standard6(board)) => print!("{}", trace_eval(&*board)),
+                Ok(TakGame::Standard7(board)) => print!("{}", trace_eval(&*board)),
+                Ok(TakGame::Standard8(board)) => print!("{}", trace_eval(&*board)),
+                Ok(_) => println!("Evaluation is only available for sizes 5 through 8"),
+                Err(e) => println!("Unable to create game with tps: \n{}\n{}", tps, e),
+            }
+            return;
+        } else if arg1 == "analyze" {
+            if let Err(e) = analyze(&args[2..]) {
+                println!("Analysis failed: {}", e);
+            }
+            return;
+        } else if arg1 == "selfplay" {
+            if let Err(e) = selfplay(&args[2..]) {
+                println!("Self-play failed: {}", e);
+            }
+            return;
         } else if arg1 == "playtak" {
+            let ponder = args[2..].iter().any(|arg| arg == "--ponder");
             let (s1, r1) = unbounded();
             let (s2, r2) = unbounded();
             playtak_loop(s1, r2);
-            play_game_playtak(s2, r1).unwrap();
+            play_game_playtak(s2, r1, ponder).unwrap();
             return;
         } else {
             println!("Unknown argument: {}", arg1);
@@ -110,6 +196,236 @@ pub fn main() {
     }
 }
 
+const TABLEBASE_MAX_POSITIONS: usize = 100_000_000;
+
+fn build_tablebase<T: TakBoard + Clone>(board: &T, horizon: usize, path: &str) -> Result<()> {
+    let time = Instant::now();
+    let tablebase = tablebase::Tablebase::generate(board, horizon, TABLEBASE_MAX_POSITIONS)?;
+    let (wins, losses, draws) = tablebase.summary();
+    println!(
+        "Solved {} positions in {} ms: {} wins {} losses {} draws, complete: {}",
+        tablebase.len(),
+        time.elapsed().as_millis(),
+        wins,
+        losses,
+        draws,
+        tablebase.complete()
+    );
+    println!("Root: {:?}", tablebase.probe(board));
+    tablebase.save(path)
+}
+
+/// Board size of the first position in a tuning dataset
+fn dataset_size(path: &str) -> Result<usize> {
+    let text = std::fs::read_to_string(path)?;
+    let line = text
+        .lines()
+        .find(|l| !l.trim().is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Empty dataset"))?;
+    Ok(line.chars().filter(|&c| c == '/').count() + 1)
+}
+
+fn tune_weights<T: TakBoard>(dataset: &str, output: &str, iterations: usize) -> Result<()> {
+    let positions = tune::read_dataset::<T, _>(dataset)?;
+    anyhow::ensure!(!positions.is_empty(), "No quiet positions in dataset");
+    let mut tuner = tune::Tuner::new(positions);
+    // A corrupt output file would only fail to save once tuning is done, so fail now.
+    // A valid file without this board size starts from the default weights.
+    let initial = if std::path::Path::new(output).exists() {
+        let file = WeightsFile::read(output)?;
+        if file.sizes().any(|size| size == T::SIZE) {
+            file.weights()?
+        } else {
+            Weights::default()
+        }
+    } else {
+        Weights::default()
+    };
+    let k = tuner.fit_k(&initial);
+    println!(
+        "Tuning {} positions, k = {:.4}, initial error {:.6}",
+        tuner.len(),
+        k,
+        tuner.error(&initial)
+    );
+    let time = Instant::now();
+    let (tuned, err) = tuner.tune(&initial, iterations);
+    println!(
+        "Final error {:.6} after {} ms",
+        err,
+        time.elapsed().as_millis()
+    );
+    tuned.to_file(output)
+}
+
+fn trace_eval<T: TakBoard>(board: &T) -> eval::EvalTrace {
+    Weights::default().trace(board, 0)
+}
+
+fn analyze(args: &[String]) -> Result<()> {
+    let mut opts = Options::new();
+    opts.optopt("d", "depth", "Maximum search depth (default 20)", "DEPTH");
+    opts.optopt("t", "time", "Maximum search time in seconds (default 60)", "SECONDS");
+    opts.optopt("m", "hash", "Transposition table size in MB (default 256)", "MB");
+    opts.optopt("j", "threads", "Number of search threads (default 1)", "THREADS");
+    opts.optopt("p", "multipv", "Number of best root moves to report (default 1)", "LINES");
+    opts.optopt(
+        "",
+        "tt",
+        "Load the transposition table from this file if it exists, and save it after the search",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "tablebase",
+        "Score the solved positions in this tablebase instead of searching them",
+        "FILE",
+    );
+    opts.optopt("", "nnue", "Evaluate with the network in this TZNN file", "FILE");
+    opts.optflag("h", "help", "Print the help text");
+    let matches = opts.parse(args)?;
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print!("{}", opts.usage("Usage: topaz analyze <tps|saved name> [options]"));
+        return Ok(());
+    }
+    let tps = matches.free.join(" ");
+    let tps = saved_tps(&tps).unwrap_or(&tps);
+    match TakGame::try_from_tps(tps)? {
+        TakGame::Standard3(board) => analyze_position(*board, &matches),
+        TakGame::Standard4(board) => analyze_position(*board, &matches),
+        TakGame::Standard5(board) => analyze_position(*board, &matches),
+        TakGame::Standard6(board) => analyze_position(*board, &matches),
+        TakGame::Standard7(board) => analyze_position(*board, &matches),
+        TakGame::Standard8(board) => analyze_position(*board, &matches),
+        _ => Err(anyhow::anyhow!("Analysis is only available for sizes 3 through 8")),
+    }
+}
+
+fn analyze_position<T: TakBoard + Clone + Send>(
+    mut board: T,
+    matches: &getopts::Matches,
+) -> Result<()> {
+    let depth = matches.opt_get_default("d", 20)?;
+    let time = matches.opt_get_default("t", 60)?;
+    let threads = matches.opt_get_default("j", 1)?;
+    let multi_pv = matches.opt_get_default("p", 1)?;
+    let tt_file = matches.opt_str("tt");
+    let table = match tt_file {
+        Some(ref path) if std::path::Path::new(path).exists() => {
+            anyhow::ensure!(
+                !matches.opt_present("m"),
+                "--hash cannot be used when the --tt file exists, its table keeps the size it was saved with"
+            );
+            let table = HashTable::load(path, T::SIZE)?;
+            println!("Loaded {} MB transposition table from {}", table.size_mb(), path);
+            table
+        }
+        _ => HashTable::with_mb(matches.opt_get_default("m", DEFAULT_HASH_MB)?),
+    };
+    let table = Arc::new(table);
+    let mut info = SearchInfo::new(depth, 0)
+        .shared_table(table.clone())
+        .max_time(time * 1000)
+        .multi_pv(multi_pv);
+    if let Some(path) = matches.opt_str("tablebase") {
+        let tablebase = tablebase::Tablebase::load(&path)?;
+        anyhow::ensure!(
+            tablebase.size() == T::SIZE && tablebase.komi() == board.komi(),
+            "Tablebase is for size {} with komi {}, not size {} with komi {}",
+            tablebase.size(),
+            tablebase.komi(),
+            T::SIZE,
+            board.komi()
+        );
+        println!("Loaded {} tablebase positions from {}", tablebase.len(), path);
+        info = info.tablebase(Arc::new(tablebase));
+    }
+    let outcome = match matches.opt_str("nnue") {
+        Some(path) => {
+            let eval = NnueEvaluator::<T>::load(&path)?;
+            println!("Loaded network from {}", path);
+            search_parallel(&mut board, &eval, &mut info, threads)
+        }
+        None => search_parallel(&mut board, &Weights::<T>::default(), &mut info, threads),
+    }
+    .ok_or_else(|| anyhow::anyhow!("Search did not complete a single depth"))?;
+    println!("info {}", outcome);
+    if info.stats.tablebase_hits > 0 {
+        println!("Tablebase hits: {}", info.stats.tablebase_hits);
+    }
+    if let Some(path) = tt_file {
+        table.save(&path, T::SIZE)?;
+        println!("Saved transposition table to {}", path);
+    }
+    Ok(())
+}
+
+fn selfplay(args: &[String]) -> Result<()> {
+    let mut opts = Options::new();
+    opts.optopt("g", "games", "Number of games to play (default 100)", "N");
+    opts.optopt("s", "size", "Board size (default 6)", "SIZE");
+    opts.optopt("d", "depth", "Maximum search depth (default 6)", "DEPTH");
+    opts.optopt("n", "nodes", "Maximum nodes per search", "NODES");
+    opts.optopt("r", "random", "Random opening plies (default 4)", "PLIES");
+    opts.optopt("k", "komi", "Komi in half flats (default 0)", "KOMI");
+    opts.optopt("m", "hash", "Transposition table size in MB (default 16)", "MB");
+    opts.optflag("", "noise", "Add noise to the evaluation of every game");
+    opts.optflag("h", "help", "Print the help text");
+    let matches = opts.parse(args)?;
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        print!("{}", opts.usage("Usage: topaz selfplay <output file> [options]"));
+        return Ok(());
+    }
+    let mut config = selfplay::SelfPlayConfig::default();
+    config.max_depth = matches.opt_get_default("d", config.max_depth)?;
+    config.max_nodes = matches.opt_get("n")?;
+    config.random_plies = matches.opt_get_default("r", config.random_plies)?;
+    config.komi = matches.opt_get_default("k", config.komi)?;
+    config.hash_mb = matches.opt_get_default("m", config.hash_mb)?;
+    config.add_noise = matches.opt_present("noise");
+    let games: usize = matches.opt_get_default("g", 100)?;
+    let size: usize = matches.opt_get_default("s", 6)?;
+    match size {
+        5 => selfplay_games::<Board5>(&config, games, &matches.free[0]),
+        6 => selfplay_games::<Board6>(&config, games, &matches.free[0]),
+        7 => selfplay_games::<Board7>(&config, games, &matches.free[0]),
+        8 => selfplay_games::<Board8>(&config, games, &matches.free[0]),
+        _ => Err(anyhow::anyhow!("Unsupported board size: {}", size)),
+    }
+}
+
+fn selfplay_games<T: TakBoard>(
+    config: &selfplay::SelfPlayConfig,
+    games: usize,
+    path: &str,
+) -> Result<()> {
+    use rand_core::SeedableRng;
+    let mut seed = [0; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let mut rng = rand_xoshiro::Xoshiro256PlusPlus::from_seed(seed);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let time = Instant::now();
+    let mut positions = 0;
+    for game in 1..=games {
+        let record = selfplay::play_game::<T, _>(config, &mut rng)?;
+        record.write(&mut file)?;
+        positions += record.len();
+        println!(
+            "Game {}/{}: {:?} after {} positions, {} total in {} s",
+            game,
+            games,
+            record.result(),
+            record.len(),
+            positions,
+            time.elapsed().as_secs()
+        );
+    }
+    Ok(())
+}
+
 fn saved_tps(name: &str) -> Option<&str> {
     let s = match name {
         "alion1" => "2,1221122,1,1,1,2S/1,1,1,x,1C,1111212/x2,2,212,2C,11/2,2,x2,1,1/x3,1,1,x/x2,2,21,x,112S 2 32",
@@ -176,7 +492,7 @@ fn search_efficiency(names: &[(&str, usize)], save: bool) -> Result<Vec<usize>>
     if save {
         let mut f = std::fs::File::create("node_counts.csv")?;
         for ((name, depth), nodes) in names.iter().zip(vec.iter()) {
-            write!(&mut f, "{},{},{}\n", name, depth, nodes)?;
+            writeln!(&mut f, "{},{},{}", name, depth, nodes)?;
         }
     }
     Ok(vec)
@@ -255,7 +571,7 @@ fn proof_interactive<T: TakBoard>(mut search: TinueSearch<T>) -> Result<()> {
 fn play_game_cmd(mut computer_turn: bool) {
     let mut board = Board6::new();
     let eval = Evaluator6 {};
-    while let None = board.game_result() {
+    while board.game_result().is_none() {
         println!("{:?}", &board);
         if computer_turn {
             let mut info = SearchInfo::new(6, 5000);
@@ -283,6 +599,11 @@ fn play_game_cmd(mut computer_turn: bool) {
     }
 }
 
+/// Milliseconds lost to communication on every move
+const MOVE_OVERHEAD: u64 = 50;
+/// Below this many milliseconds the engine only searches briefly
+const EMERGENCY_TIME: u64 = 1000;
+
 struct TimeLeft {
     wtime: u64,
     btime: u64,
@@ -312,88 +633,466 @@ impl TimeLeft {
         }
         ret
     }
-    fn use_time(&self, est_plies: usize, side_to_move: Color) -> u64 {
+    /// Soft and hard search time limits in milliseconds
+    fn use_time(&self, est_plies: usize, side_to_move: Color) -> (u64, u64) {
         let (time_bank, inc) = match side_to_move {
             Color::White => (self.wtime, self.winc),
             Color::Black => (self.btime, self.binc),
         };
-        let use_bank = time_bank / (est_plies + 2) as u64 / 1000;
-        use_bank + inc / 1000
+        // Time spent sending the move is lost, so never plan on using it
+        let usable = time_bank.saturating_sub(MOVE_OVERHEAD);
+        if usable < EMERGENCY_TIME {
+            // Nearly flagging, move quickly and let the increment rebuild the bank
+            let time = std::cmp::min(usable / 10 + inc / 2, usable / 2);
+            return (time, time);
+        }
+        let soft = usable / (est_plies + 2) as u64 + inc * 3 / 4;
+        let soft = std::cmp::min(soft, usable / 4);
+        let hard = std::cmp::min(soft * 3, usable / 2);
+        (soft, hard)
     }
 }
 
-fn play_game_tei(receiver: Receiver<TeiCommand>, init: GameInitializer) -> Result<()> {
-    let mut board = init.get_board();
-    let mut info = SearchInfo::new(init.max_depth, init.hash_size);
-    let mut eval = Weights6::default();
+const GO_KEYWORDS: [&str; 11] = [
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "movetime",
+    "infinite",
+    "searchmoves",
+    "ponder",
+];
+
+/// Search limits of a TEI go command. Every limit given is honored, and the clock
+/// is only used when it is given or when there are no other limits.
+struct GoOptions {
+    time_left: TimeLeft,
+    has_clock: bool,
+    depth: Option<usize>,
+    nodes: Option<usize>,
+    movetime: Option<u64>,
+    infinite: bool,
+    ponder: bool,
+    searchmoves: Vec<String>,
+}
+
+impl GoOptions {
+    fn parse(tei_str: &str) -> Result<Self> {
+        let mut go = Self {
+            time_left: TimeLeft::new(tei_str),
+            has_clock: false,
+            depth: None,
+            nodes: None,
+            movetime: None,
+            infinite: false,
+            ponder: false,
+            searchmoves: Vec::new(),
+        };
+        let mut tokens = tei_str.split_whitespace().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                "wtime" | "btime" => go.has_clock = true,
+                "depth" => go.depth = Some(go_value(token, tokens.next())?),
+                "nodes" => go.nodes = Some(go_value(token, tokens.next())?),
+                "movetime" => go.movetime = Some(go_value(token, tokens.next())?),
+                "infinite" => go.infinite = true,
+                "ponder" => go.ponder = true,
+                "searchmoves" => {
+                    while let Some(m) = tokens.next_if(|t| !GO_KEYWORDS.contains(t)) {
+                        go.searchmoves.push(m.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        anyhow::ensure!(go.depth != Some(0), "Search depth must be at least 1");
+        anyhow::ensure!(
+            go.depth.is_none_or(|d| d <= MAX_DEPTH),
+            "Search depth must be at most {}",
+            MAX_DEPTH
+        );
+        Ok(go)
+    }
+    fn search_info<T: TakBoard>(&self, board: &T, max_depth: usize) -> Result<SearchInfo> {
+        let mut info = SearchInfo::new(self.depth.unwrap_or(max_depth), 0);
+        if self.infinite {
+            info = info.max_time(u64::MAX);
+        } else if let Some(movetime) = self.movetime {
+            info = info.max_time(movetime);
+        } else if self.has_clock || (self.depth.is_none() && self.nodes.is_none()) {
+            let low_flats = std::cmp::min(
+                board.pieces_reserve(Color::White),
+                board.pieces_reserve(Color::Black),
+            );
+            let est_plies = low_flats * 2;
+            let (soft, hard) = self.time_left.use_time(est_plies, board.side_to_move());
+            info = info.time_limits(soft, hard);
+        } else {
+            info = info.max_time(u64::MAX);
+        }
+        if let Some(nodes) = self.nodes {
+            info = info.max_nodes(nodes);
+        }
+        info = info.ponder(self.ponder);
+        if !self.searchmoves.is_empty() {
+            let mut moves = Vec::new();
+            for ptn in self.searchmoves.iter() {
+                match GameMove::try_from_ptn(ptn, board) {
+                    Some(m) if board.legal_move(m) => moves.push(m),
+                    _ => anyhow::bail!("Illegal move in searchmoves: {}", ptn),
+                }
+            }
+            info = info.root_moves(moves);
+        }
+        Ok(info)
+    }
+}
+
+fn go_value<V: std::str::FromStr>(name: &str, value: Option<&str>) -> Result<V> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid value for go {}", name))
+}
+
+/// Parses `position startpos [moves ...]` or `position tps <tps> [moves ...]`,
+/// checking that every move is legal in the position it is played from
+fn parse_position<T: TakBoard>(line: &str, init: &GameInitializer) -> Result<T> {
+    let mut tokens = line.split_whitespace().skip(1);
+    let mut board: T = match tokens.next() {
+        Some("startpos") => init.get_board(),
+        Some("tps") => {
+            // Board, side to move and move number
+            let tps: Vec<_> = tokens.by_ref().take(3).collect();
+            anyhow::ensure!(tps.len() == 3, "Incomplete tps in position command");
+            T::try_from_tps(&tps.join(" "))?.with_komi(init.komi)
+        }
+        Some(other) => anyhow::bail!("Unknown position type: {}", other),
+        None => anyhow::bail!("Missing position type"),
+    };
+    match tokens.next() {
+        Some("moves") => {}
+        Some(other) => anyhow::bail!("Expected moves, found {}", other),
+        None => return Ok(board),
+    }
+    for (idx, ptn) in tokens.enumerate() {
+        match GameMove::try_from_ptn(ptn, &board) {
+            Some(m) if board.legal_move(m) => {
+                board.do_move(m);
+            }
+            _ => anyhow::bail!("Illegal move {} at index {} of the move list", ptn, idx),
+        }
+    }
+    Ok(board)
+}
+
+fn play_game_tei<T: TakBoard + Clone + Send>(receiver: Receiver<TeiCommand>, mut init: GameInitializer) -> Result<()> {
+    let mut board: T = init.get_board();
+    let mut table = Arc::new(HashTable::with_mb(init.hash_mb));
+    let mut eval = init.get_eval();
+    // Commands that arrived while searching
+    let mut pending = VecDeque::new();
 
     loop {
-        let message = receiver.recv()?;
+        let message = match pending.pop_front() {
+            Some(message) => message,
+            None => receiver.recv()?,
+        };
         match message {
             TeiCommand::Go(s) => {
-                let low_flats = std::cmp::min(
-                    board.pieces_reserve(Color::White),
-                    board.pieces_reserve(Color::Black),
-                );
-                let est_plies = low_flats * 2;
-                let time_left = TimeLeft::new(&s);
-                let use_time = time_left.use_time(est_plies, board.side_to_move());
-                info = SearchInfo::new(init.max_depth, 0)
-                    .take_table(&mut info)
-                    .max_time(use_time);
+                let parsed = GoOptions::parse(&s)
+                    .and_then(|go| Ok((go.search_info(&board, init.max_depth)?, go.infinite)));
+                let (mut info, infinite) = match parsed {
+                    Ok((info, infinite)) => (
+                        info.shared_table(table.clone())
+                            .input_stream(receiver.clone())
+                            .multi_pv(init.multi_pv),
+                        infinite,
+                    ),
+                    Err(e) => {
+                        println!("info string {}", e);
+                        continue;
+                    }
+                };
                 if board.ply() == 8 || board.ply() == 9 {
-                    eval = Weights6::default();
+                    eval = init.get_eval();
+                }
+                let res = search_parallel(&mut board, &eval, &mut info, init.threads);
+                pending.extend(info.take_pending());
+                // An infinite or pondering search only reports its move once told to
+                let mut waiting = (infinite || info.pondering()) && !info.interrupted();
+                while waiting {
+                    match receiver.recv()? {
+                        TeiCommand::Stop => waiting = false,
+                        TeiCommand::PonderHit => waiting = infinite,
+                        command => {
+                            waiting = !matches!(command, TeiCommand::Quit);
+                            pending.push_back(command);
+                        }
+                    }
                 }
-                let res = search(&mut board, &eval, &mut info);
                 if let Some(outcome) = res {
                     println!("info {}", outcome);
-                    println!(
-                        "bestmove {}",
-                        outcome
-                            .best_move()
-                            .expect("Could not find best move!")
-                            .trim_end_matches('*')
-                    );
+                    let best_move = outcome.best_move().expect("Could not find best move!");
+                    match outcome.pv().get(1) {
+                        Some(reply) if init.ponder => println!(
+                            "bestmove {} ponder {}",
+                            best_move.trim_end_matches('*'),
+                            reply.to_ptn::<T>().trim_end_matches('*')
+                        ),
+                        _ => println!("bestmove {}", best_move.trim_end_matches('*')),
+                    }
                 } else {
                     println!("Something went wrong, search failed!");
                 }
             }
-            TeiCommand::Position(s) => {
-                board = init.get_board();
-                for m in s.split_whitespace() {
-                    if let Some(m) = GameMove::try_from_ptn(m, &board) {
-                        board.do_move(m);
-                    }
-                }
-            }
+            TeiCommand::Position(s) => match parse_position(&s, &init) {
+                Ok(position) => board = position,
+                // Keep the last good position rather than search a half applied one
+                Err(e) => println!("info string {}", e),
+            },
             TeiCommand::NewGame(_size) => {
-                info.clear_tt();
+                table.clear();
                 if init.add_noise {
                     println!("Adding noise!");
                     eval.add_noise();
                 }
             }
+            TeiCommand::ResizeHash(mb) => match Arc::get_mut(&mut table) {
+                Some(table) => table.resize(mb),
+                // A finished search may still hold a handle, so make a fresh table instead
+                None => table = Arc::new(HashTable::with_mb(mb)),
+            },
+            TeiCommand::Threads(threads) => init.threads = threads,
+            TeiCommand::MaxDepth(depth) => init.max_depth = depth,
+            TeiCommand::Ponder(ponder) => init.ponder = ponder,
+            TeiCommand::MultiPv(lines) => init.multi_pv = lines,
+            TeiCommand::Eval => {
+                for line in eval.trace(&board, 0).to_string().lines() {
+                    println!("info string {}", line);
+                }
+            }
             TeiCommand::Quit => {
                 break;
             }
-            _ => println!("Unknown command: {:?}", message),
+            // Nothing to stop when no search is running
+            TeiCommand::Stop | TeiCommand::PonderHit => {}
         }
     }
     Ok(())
 }
 
+const MAX_THREADS: usize = 256;
+const DEFAULT_HASH_MB: usize = 256;
+const DEFAULT_MAX_DEPTH: usize = 80;
+const MAX_DEPTH: usize = 128;
+const _: () = assert!(MAX_DEPTH <= MAX_TT_DEPTH);
+const MAX_MULTI_PV: usize = 64;
+
+#[derive(Debug)]
+enum OptionKind {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Text,
+}
+
+#[derive(Debug)]
+enum OptionValue {
+    Spin(i64),
+    Check(bool),
+    Text(String),
+}
+
+/// An option the engine advertises in response to tei and accepts with setoption
+#[derive(Debug)]
+struct TeiOption {
+    name: &'static str,
+    kind: OptionKind,
+}
+
+static TEI_OPTIONS: [TeiOption; 8] = [
+    TeiOption {
+        name: "HalfKomi",
+        kind: OptionKind::Spin {
+            default: 0,
+            min: 0,
+            max: 12,
+        },
+    },
+    TeiOption {
+        name: "Hash",
+        kind: OptionKind::Spin {
+            default: DEFAULT_HASH_MB as i64,
+            min: 1,
+            max: MAX_HASH_MB as i64,
+        },
+    },
+    TeiOption {
+        name: "Threads",
+        kind: OptionKind::Spin {
+            default: 1,
+            min: 1,
+            max: MAX_THREADS as i64,
+        },
+    },
+    TeiOption {
+        name: "MaxDepth",
+        kind: OptionKind::Spin {
+            default: DEFAULT_MAX_DEPTH as i64,
+            min: 1,
+            max: MAX_DEPTH as i64,
+        },
+    },
+    TeiOption {
+        name: "MultiPV",
+        kind: OptionKind::Spin {
+            default: 1,
+            min: 1,
+            max: MAX_MULTI_PV as i64,
+        },
+    },
+    TeiOption {
+        name: "Ponder",
+        kind: OptionKind::Check { default: false },
+    },
+    TeiOption {
+        name: "AddNoise",
+        kind: OptionKind::Check { default: false },
+    },
+    TeiOption {
+        name: "WeightsFile",
+        kind: OptionKind::Text,
+    },
+];
+
+/// Old option names that setoption still accepts, along with the option they set
+static TEI_ALIASES: [(&str, &str); 1] = [("Komi", "HalfKomi")];
+
+impl TeiOption {
+    fn find(name: &str) -> Option<&'static TeiOption> {
+        let name = TEI_ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .map_or(name, |(_, option)| option);
+        TEI_OPTIONS.iter().find(|o| o.name.eq_ignore_ascii_case(name))
+    }
+    fn parse(&self, value: &str) -> Result<OptionValue> {
+        match self.kind {
+            OptionKind::Spin { min, max, .. } => match value.parse() {
+                Ok(v) if (min..=max).contains(&v) => Ok(OptionValue::Spin(v)),
+                _ => anyhow::bail!("{} must be between {} and {}, got {}", self.name, min, max, value),
+            },
+            OptionKind::Check { .. } => match value {
+                "true" => Ok(OptionValue::Check(true)),
+                "false" => Ok(OptionValue::Check(false)),
+                _ => anyhow::bail!("{} must be true or false, got {}", self.name, value),
+            },
+            OptionKind::Text => Ok(OptionValue::Text(value.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for TeiOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+        match self.kind {
+            OptionKind::Spin { default, min, max } => {
+                write!(f, "spin default {} min {} max {}", default, min, max)
+            }
+            OptionKind::Check { default } => write!(f, "check default {}", default),
+            OptionKind::Text => write!(f, "string default <empty>"),
+        }
+    }
+}
+
+/// Parses `setoption name <name> value <value>`. Names may contain spaces, and
+/// string values are everything after `value`, so paths may too.
+fn parse_setoption(line: &str) -> Result<(&'static TeiOption, OptionValue)> {
+    let rest = line
+        .strip_prefix("setoption")
+        .map(str::trim_start)
+        .and_then(|rest| rest.strip_prefix("name "))
+        .ok_or_else(|| anyhow::anyhow!("Expected setoption name <name> value <value>"))?;
+    let mut parts = rest.splitn(2, " value");
+    let name = parts.next().unwrap_or("").trim();
+    let value = parts.next().unwrap_or("").trim();
+    let option = TeiOption::find(name).ok_or_else(|| anyhow::anyhow!("Unknown option: {}", name))?;
+    Ok((option, option.parse(value)?))
+}
+
 fn identify() {
     println!("id name Topaz");
     println!("id author Justin Kur");
-    println!("option name Komi type spin default 0 min 0 max 12");
+    for option in TEI_OPTIONS.iter() {
+        println!("{}", option);
+    }
     println!("teiok");
 }
 
+struct TeiEngine {
+    size: usize,
+    init: GameInitializer,
+    sender: Sender<TeiCommand>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl TeiEngine {
+    /// Starts a search thread for the given board size, or None if the size is unsupported
+    fn spawn(size: usize, init: GameInitializer) -> Option<Self> {
+        let (sender, r) = unbounded();
+        let thread_init = init.clone();
+        let handle = match size {
+            5 => thread::spawn(move || play_game_tei::<Board5>(r, thread_init).unwrap()),
+            6 => thread::spawn(move || play_game_tei::<Board6>(r, thread_init).unwrap()),
+            7 => thread::spawn(move || play_game_tei::<Board7>(r, thread_init).unwrap()),
+            8 => thread::spawn(move || play_game_tei::<Board8>(r, thread_init).unwrap()),
+            _ => return None,
+        };
+        Some(Self {
+            size,
+            init,
+            sender,
+            handle,
+        })
+    }
+    /// Passes on the settings that can change while a game is running. The rest
+    /// take effect when a new engine is started by the next teinewgame.
+    fn update(&mut self, init: &GameInitializer) {
+        if self.init.hash_mb != init.hash_mb {
+            self.init.hash_mb = init.hash_mb;
+            let _ = self.sender.send(TeiCommand::ResizeHash(init.hash_mb));
+        }
+        if self.init.threads != init.threads {
+            self.init.threads = init.threads;
+            let _ = self.sender.send(TeiCommand::Threads(init.threads));
+        }
+        if self.init.max_depth != init.max_depth {
+            self.init.max_depth = init.max_depth;
+            let _ = self.sender.send(TeiCommand::MaxDepth(init.max_depth));
+        }
+        if self.init.ponder != init.ponder {
+            self.init.ponder = init.ponder;
+            let _ = self.sender.send(TeiCommand::Ponder(init.ponder));
+        }
+        if self.init.multi_pv != init.multi_pv {
+            self.init.multi_pv = init.multi_pv;
+            let _ = self.sender.send(TeiCommand::MultiPv(init.multi_pv));
+        }
+    }
+    fn quit(self) {
+        // The thread may have already exited if it hit an error
+        let _ = self.sender.send(TeiCommand::Quit);
+        let _ = self.handle.join();
+    }
+}
+
 fn tei_loop() {
-    let (sender, r) = unbounded();
-    let mut receiver = Some(r);
+    let mut engine: Option<TeiEngine> = None;
     let mut buffer = String::new();
-    let mut init = GameInitializer::new(2 << 22, 80, 0, false);
+    let mut init = GameInitializer::new(DEFAULT_HASH_MB, DEFAULT_MAX_DEPTH, 0, false);
     identify();
     loop {
         std::io::stdin()
@@ -405,35 +1104,65 @@ fn tei_loop() {
         } else if line == "isready" {
             println!("readyok");
         } else if line == "quit" {
-            sender.send(TeiCommand::Quit).unwrap();
+            if let Some(engine) = engine.take() {
+                engine.quit();
+            }
             break;
-        } else if line == "stop" {
-            sender.send(TeiCommand::Stop).unwrap();
-        } else if line.starts_with("position") {
-            sender.send(TeiCommand::Position(line.to_string())).unwrap();
-        } else if line.starts_with("go") {
-            sender.send(TeiCommand::Go(line.to_string())).unwrap();
         } else if line.starts_with("teinewgame") {
-            let size = line
-                .split_whitespace()
-                .nth(1)
-                .expect("Bad new game command?")
-                .parse()
-                .expect("Failed to parse size!");
-            if let Some(recv) = receiver.take() {
-                let init = init.clone();
-                thread::spawn(move || {
-                    play_game_tei(recv, init).unwrap();
-                });
-            }
-            sender.send(TeiCommand::NewGame(size)).unwrap();
+            let size = match line.split_whitespace().nth(1).map(|s| s.parse::<usize>()) {
+                Some(Ok(size)) => size,
+                _ => {
+                    println!("info string Unable to parse board size: {}", line);
+                    buffer.clear();
+                    continue;
+                }
+            };
+            // Reuse the running engine only if nothing about the game has changed
+            let reuse = engine
+                .as_ref()
+                .map(|e| e.size == size && e.init == init)
+                .unwrap_or(false);
+            if !reuse {
+                if let Some(old) = engine.take() {
+                    old.quit();
+                }
+                engine = TeiEngine::spawn(size, init.clone());
+            }
+            if let Some(ref engine) = engine {
+                engine.sender.send(TeiCommand::NewGame(size)).unwrap();
+            } else {
+                println!("info string Unsupported board size: {}", size);
+            }
+        } else if line == "stop"
+            || line == "ponderhit"
+            || line == "eval"
+            || line.starts_with("position")
+            || line.starts_with("go")
+        {
+            if let Some(ref engine) = engine {
+                let command = if line == "stop" {
+                    TeiCommand::Stop
+                } else if line == "ponderhit" {
+                    TeiCommand::PonderHit
+                } else if line == "eval" {
+                    TeiCommand::Eval
+                } else if line.starts_with("position") {
+                    TeiCommand::Position(line.to_string())
+                } else {
+                    TeiCommand::Go(line.to_string())
+                };
+                engine.sender.send(command).unwrap();
+            } else {
+                println!("info string No game in progress, send teinewgame first");
+            }
         } else if line.starts_with("setoption") {
-            let mut iter = line.split_whitespace();
-            let name = iter.nth(2).unwrap();
-            let value = iter.nth(1).unwrap();
-            if name == "Komi" {
-                init.komi = value.parse().unwrap();
-                println!("Setting komi to {}", init.komi);
+            match parse_setoption(line).and_then(|(option, value)| init.set_option(option, value)) {
+                Ok(()) => {
+                    if let Some(ref mut engine) = engine {
+                        engine.update(&init);
+                    }
+                }
+                Err(e) => println!("info string {}", e),
             }
         } else {
             println!("Unknown Tei Command: {}", buffer);
@@ -442,49 +1171,119 @@ fn tei_loop() {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 struct GameInitializer {
-    hash_size: usize,
+    /// Transposition table size in megabytes
+    hash_mb: usize,
     max_depth: usize,
     komi: u8,
     add_noise: bool,
+    weights_file: Option<String>,
+    threads: usize,
+    ponder: bool,
+    multi_pv: usize,
 }
 
 impl GameInitializer {
-    fn new(hash_size: usize, max_depth: usize, komi: u8, add_noise: bool) -> Self {
+    fn new(hash_mb: usize, max_depth: usize, komi: u8, add_noise: bool) -> Self {
         Self {
-            hash_size,
+            hash_mb,
             max_depth,
             komi,
             add_noise,
+            weights_file: None,
+            threads: 1,
+            ponder: false,
+            multi_pv: 1,
         }
     }
-    fn get_board(&self) -> Board6 {
-        Board6::new().with_komi(self.komi)
+    fn set_option(&mut self, option: &TeiOption, value: OptionValue) -> Result<()> {
+        match (option.name, value) {
+            ("HalfKomi", OptionValue::Spin(komi)) => self.komi = komi as u8,
+            ("Hash", OptionValue::Spin(mb)) => self.hash_mb = mb as usize,
+            ("Threads", OptionValue::Spin(threads)) => self.threads = threads as usize,
+            ("MaxDepth", OptionValue::Spin(depth)) => self.max_depth = depth as usize,
+            ("MultiPV", OptionValue::Spin(lines)) => self.multi_pv = lines as usize,
+            ("Ponder", OptionValue::Check(ponder)) => self.ponder = ponder,
+            ("AddNoise", OptionValue::Check(add_noise)) => self.add_noise = add_noise,
+            ("WeightsFile", OptionValue::Text(path)) => {
+                if path.is_empty() || path == "<empty>" {
+                    self.weights_file = None;
+                } else {
+                    let file = WeightsFile::read(&path)?;
+                    let sizes: Vec<_> = file.sizes().map(|s| s.to_string()).collect();
+                    println!(
+                        "info string Loaded weights for sizes [{}] from {}",
+                        sizes.join(", "),
+                        path
+                    );
+                    self.weights_file = Some(path);
+                }
+            }
+            _ => unreachable!("Option {} has no setter", option.name),
+        }
+        Ok(())
+    }
+    fn get_board<T: TakBoard>(&self) -> T {
+        T::start_position().with_komi(self.komi)
+    }
+    /// Loads the configured weights file, falling back to the default weights
+    /// if it is unset or has no section for this board size
+    fn get_eval<T: TakBoard>(&self) -> Weights<T> {
+        if let Some(ref path) = self.weights_file {
+            match Weights::from_file(path) {
+                Ok(weights) => return weights,
+                Err(e) => println!("info string {}, using default weights", e),
+            }
+        }
+        Weights::default()
     }
 }
 
-fn play_game_playtak(server_send: Sender<String>, server_recv: Receiver<TeiCommand>) -> Result<()> {
+fn play_game_playtak(
+    server_send: Sender<String>,
+    server_recv: Receiver<TeiCommand>,
+    ponder: bool,
+) -> Result<()> {
     const MAX_DEPTH: usize = 8;
     const KOMI: u8 = 0;
     let mut board = Board6::new().with_komi(KOMI);
-    let mut info = SearchInfo::new(MAX_DEPTH, 5_000_000);
+    let table = Arc::new(HashTable::new(5_000_000));
     let eval = Weights6::default();
     // eval.add_noise();
     // let eval = Evaluator6 {};
+    // Commands that arrived while pondering
+    let mut pending = VecDeque::new();
     loop {
-        let message = server_recv.recv()?;
+        let message = match pending.pop_front() {
+            Some(message) => message,
+            None => server_recv.recv()?,
+        };
         match message {
             TeiCommand::Go(_) => {
-                let use_time = 15; // Todo better time management
-                info = SearchInfo::new(MAX_DEPTH, 0)
-                    .take_table(&mut info)
+                let use_time = 15_000; // Todo better time management
+                let mut info = SearchInfo::new(MAX_DEPTH, 0)
+                    .shared_table(table.clone())
                     .max_time(use_time);
                 let res = search(&mut board, &eval, &mut info);
                 if let Some(outcome) = res {
                     server_send
                         .send(outcome.best_move().expect("could not find best move!"))
                         .unwrap();
+                    // Search the expected reply on the opponent's time until the server
+                    // sends the next position, leaving the results in the shared table
+                    if let (true, [best, reply, ..]) = (ponder, outcome.pv()) {
+                        let mut ponder_board = board.clone();
+                        ponder_board.do_move(*best);
+                        ponder_board.do_move(*reply);
+                        let mut info = SearchInfo::new(MAX_DEPTH, 0)
+                            .shared_table(table.clone())
+                            .input_stream(server_recv.clone())
+                            .ponder(true)
+                            .quiet(true);
+                        search(&mut ponder_board, &eval, &mut info);
+                        pending.extend(info.take_pending());
+                    }
                 } else {
                     println!("Something went wrong, search failed!");
                 }
@@ -507,7 +1306,7 @@ fn play_game_playtak(server_send: Sender<String>, server_recv: Receiver<TeiComma
 }
 
 fn playtak_loop(engine_send: Sender<TeiCommand>, engine_recv: Receiver<String>) {
-    static OPP: &'static str = "TakticianBot";
+    static OPP: &str = "TakticianBot";
     let (user, pass) = playtak_auth().expect("Could not read properly formatted .env file");
     std::thread::spawn(move || {
         let mut com = telnet::Telnet::connect(("playtak.com", 10_000), 2048).unwrap();
@@ -527,7 +1326,7 @@ fn playtak_loop(engine_send: Sender<TeiCommand>, engine_recv: Receiver<String>)
             match com.read_nonblocking() {
                 Ok(event) => match event {
                     Event::Data(buffer) => {
-                        let s = std::str::from_utf8(&(*buffer)).unwrap();
+                        let s = std::str::from_utf8(&buffer).unwrap();
                         print!("{}", s);
                         for line in s.lines() {
                             if line.starts_with("Welcome!") {
@@ -535,7 +1334,7 @@ fn playtak_loop(engine_send: Sender<TeiCommand>, engine_recv: Receiver<String>)
                                 let login_s = format!("Login {} {}\n", user, pass);
                                 com.write(login_s.as_bytes()).unwrap();
                             } else if line.starts_with("Game#") {
-                                let rest = line.splitn(2, " ").nth(1);
+                                let rest = line.split_once(" ").map(|x| x.1);
                                 if let Some(rest) = rest {
                                     if rest.starts_with("P") || rest.starts_with("M") {
                                         moves.push(rest.to_string());
@@ -642,3 +1441,111 @@ fn playtak_auth() -> Option<(String, String)> {
     }
     Some((username?, password?))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn init() -> GameInitializer {
+        GameInitializer::new(DEFAULT_HASH_MB, DEFAULT_MAX_DEPTH, 4, false)
+    }
+
+    #[test]
+    fn position_startpos() {
+        let board: Board6 = parse_position("position startpos", &init()).unwrap();
+        assert_eq!(board.to_tps(), "x6/x6/x6/x6/x6/x6 1 1");
+        assert_eq!(board.komi(), 4);
+        let board: Board6 = parse_position("position startpos moves a1 f6 c3", &init()).unwrap();
+        assert_eq!(board.to_tps(), "x5,1/x6/x6/x2,1,x3/x6/2,x5 2 2");
+    }
+
+    #[test]
+    fn position_tps() {
+        let line = "position tps x5/x5/x2,1,x2/x5/2,x4 2 2 moves b2 c3+";
+        let board: Board5 = parse_position(line, &init()).unwrap();
+        assert_eq!(board.to_tps(), "x5/x2,1,x2/x5/x,2,x3/2,x4 2 3");
+        assert_eq!(board.komi(), 4);
+        let board: Board5 = parse_position("position tps x5/x5/x5/x5/x5 2 4", &init()).unwrap();
+        assert_eq!(board.side_to_move(), Color::Black);
+    }
+
+    #[test]
+    fn position_rejects_bad_moves() {
+        // A later illegal or unparsable move rejects the whole command
+        let err = parse_position::<Board6>("position startpos moves a1 a1", &init()).unwrap_err();
+        assert_eq!(err.to_string(), "Illegal move a1 at index 1 of the move list");
+        assert!(parse_position::<Board6>("position startpos moves a1 zz9", &init()).is_err());
+        assert!(parse_position::<Board6>("position startpos a1", &init()).is_err());
+        assert!(parse_position::<Board6>("position", &init()).is_err());
+        assert!(parse_position::<Board6>("position fen x6", &init()).is_err());
+    }
+
+    #[test]
+    fn position_incomplete_tps() {
+        let err = parse_position::<Board5>("position tps x5/x5/x5/x5/x5 1", &init()).unwrap_err();
+        assert_eq!(err.to_string(), "Incomplete tps in position command");
+        assert!(parse_position::<Board5>("position tps x5/x5/x5/x5 1 1", &init()).is_err());
+    }
+
+    #[test]
+    fn setoption_spin() {
+        let (option, value) = parse_setoption("setoption name Hash value 64").unwrap();
+        assert_eq!(option.name, "Hash");
+        assert!(matches!(value, OptionValue::Spin(64)));
+        let (option, _) = parse_setoption("setoption name halfkomi value 4").unwrap();
+        assert_eq!(option.name, "HalfKomi");
+        let (option, value) = parse_setoption("setoption name Komi value 5").unwrap();
+        assert_eq!(option.name, "HalfKomi");
+        assert!(matches!(value, OptionValue::Spin(5)));
+        let err = parse_setoption("setoption name HalfKomi value 13").unwrap_err();
+        assert_eq!(err.to_string(), "HalfKomi must be between 0 and 12, got 13");
+        assert!(parse_setoption("setoption name Threads value 0").is_err());
+        assert!(parse_setoption("setoption name Threads value many").is_err());
+    }
+
+    #[test]
+    fn setoption_check() {
+        let (_, value) = parse_setoption("setoption name Ponder value true").unwrap();
+        assert!(matches!(value, OptionValue::Check(true)));
+        let err = parse_setoption("setoption name AddNoise value yes").unwrap_err();
+        assert_eq!(err.to_string(), "AddNoise must be true or false, got yes");
+    }
+
+    #[test]
+    fn setoption_errors() {
+        let err = parse_setoption("setoption name Contempt value 10").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown option: Contempt");
+        assert!(parse_setoption("setoption Hash 64").is_err());
+    }
+
+    #[test]
+    fn setoption_string_with_spaces() {
+        let line = "setoption name WeightsFile value /home/me/my weights.txt";
+        match parse_setoption(line).unwrap() {
+            (option, OptionValue::Text(path)) => {
+                assert_eq!(option.name, "WeightsFile");
+                assert_eq!(path, "/home/me/my weights.txt");
+            }
+            _ => panic!("WeightsFile should take a string"),
+        }
+    }
+
+    #[test]
+    fn option_parse() {
+        let hash = TeiOption::find("Hash").unwrap();
+        assert!(matches!(hash.parse("1"), Ok(OptionValue::Spin(1))));
+        assert!(hash.parse(&(MAX_HASH_MB + 1).to_string()).is_err());
+        assert!(hash.parse("-5").is_err());
+        let ponder = TeiOption::find("Ponder").unwrap();
+        assert!(matches!(ponder.parse("false"), Ok(OptionValue::Check(false))));
+        assert!(ponder.parse("1").is_err());
+        assert!(TeiOption::find("Nonexistent").is_none());
+    }
+
+    #[test]
+    fn go_depth_bounds() {
+        assert_eq!(GoOptions::parse("go depth 12").unwrap().depth, Some(12));
+        assert!(GoOptions::parse("go depth 0").is_err());
+        assert!(GoOptions::parse(&format!("go depth {}", MAX_DEPTH + 1)).is_err());
+    }
+}
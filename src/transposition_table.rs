@@ -1,11 +1,30 @@
 use crate::GameMove;
+use anyhow::{ensure, Result};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 // transposition table parameters
-const TT_BUCKET_SIZE: usize = 5; // how many HashEntries for each HashTable slot
-                                 // TT_BUCKET_SIZE *
+const TT_BUCKET_SIZE: usize = 4; // how many HashEntries for each HashTable slot
+                                 // TT_BUCKET_SIZE * 16 bytes = one 64 byte cache line.
+                                 // this was 5 while entries took 24 bytes, so buckets
+                                 // now hold one entry fewer but there are more of them
 const TT_DISCARD_AGE: u8 = 5;    // adjusts which HashEntries are considered outdated
 const TT_DEPTH_SHIFT: u8 = 2;    // how depth is weighted compared to ply
 
+const MEGABYTE: usize = 1024 * 1024;
+pub const MAX_HASH_MB: usize = 65536; // largest table that may be created or loaded
+const OCCUPANCY_SAMPLE: usize = 1000; // buckets sampled for hashfull and the age histogram
+pub const TT_AGE_HISTOGRAM: usize = 16;
+pub const MAX_TT_DEPTH: usize = DEPTH_MASK as usize; // deepest entry that can be stored
+
+// saved table file header
+const MAGIC: &[u8; 4] = b"TZTT";
+const VERSION: u16 = if cfg!(feature = "legacy-tt-layout") { 1 } else { 2 };
+const HEADER_LEN: usize = 16;
+const ENTRY_LEN: usize = 16;
+const READ_CHUNK: usize = 1 << 14; // buckets allocated up front when reading a table
+
 // transposition table
 // implemented as hashtable with buckets
 //
@@ -14,6 +33,14 @@ const TT_DEPTH_SHIFT: u8 = 2;    // how depth is weighted compared to ply
 //
 // when a position is put in, an entry to replace is heuristically selected
 // from the bucket
+//
+// the table is lock-free so that it can be shared by several search threads.
+// each entry is stored as two atomic words, the packed data and the full hash xor-ed
+// with the data. if another thread overwrites an entry while it is being read, the hash
+// recovered from the mixed words will not match and the entry is treated as a miss
+//
+// wrap the table in an Arc to share it between concurrent searches, or to keep it
+// between the searches of a game, see SearchInfo::shared_table
 pub struct HashTable {
     size: usize,
     buckets: Vec<HashBucket>,
@@ -23,171 +50,439 @@ impl HashTable {
     pub fn new(size: usize) -> Self {
         Self {
             size: size / TT_BUCKET_SIZE,
-            buckets: vec![HashBucket::new(); size/TT_BUCKET_SIZE],
+            buckets: (0..size / TT_BUCKET_SIZE).map(|_| HashBucket::new()).collect(),
+        }
+    }
+
+    // creates a table taking up at most mb megabytes, but always at least one bucket
+    pub fn with_mb(mb: usize) -> Self {
+        let size = std::cmp::max(mb * MEGABYTE / std::mem::size_of::<HashBucket>(), 1);
+        Self {
+            size,
+            buckets: (0..size).map(|_| HashBucket::new()).collect(),
         }
     }
 
-    pub fn clear(&mut self) {
-        for i in 0..self.size {
-            for e_i in 0..TT_BUCKET_SIZE {
-                self.buckets[i].entries[e_i].ply = 0;
-                self.buckets[i].entries[e_i].depth_flags &= DEPTH_MASK;
+    // reallocates the table for a new memory budget, dropping all entries
+    pub fn resize(&mut self, mb: usize) {
+        // free the old table first so both are never held at once
+        self.buckets = Vec::new();
+        *self = Self::with_mb(mb);
+    }
+
+    pub fn size_mb(&self) -> usize {
+        self.size * std::mem::size_of::<HashBucket>() / MEGABYTE
+    }
+
+    pub fn clear(&self) {
+        for bucket in self.buckets.iter() {
+            for entry in bucket.entries.iter() {
+                entry.clear();
             }
         }
     }
 
+    // permille of entries in use, sampled from the first 1000 buckets
     pub fn occupancy(&self) -> usize {
-        let mut hits = 0;
-        for i in 0..1000 {
-            let bucket = &self.buckets[i];
-            for e_i in 0..TT_BUCKET_SIZE {
-                if bucket.entries[e_i].ply > 0 {
-                    hits += 1;
-                }
+        let sample = std::cmp::min(self.size, OCCUPANCY_SAMPLE);
+        if sample == 0 {
+            return 0;
+        }
+        let used = self.buckets[..sample]
+            .iter()
+            .flat_map(|bucket| bucket.entries.iter())
+            .filter(|entry| entry.is_used())
+            .count();
+        used * 1000 / (sample * TT_BUCKET_SIZE)
+    }
+
+    // counts the entries in use by how many plies before the given ply they were
+    // stored, sampled from the first 1000 buckets. the last slot holds everything
+    // at least TT_AGE_HISTOGRAM - 1 plies old. ages wrap around every 64 plies, so
+    // entries left over from later plies of another game mostly land in the last slot
+    pub fn age_histogram(&self, ply: usize) -> Vec<usize> {
+        let mut histogram = vec![0; TT_AGE_HISTOGRAM];
+        let sample = std::cmp::min(self.size, OCCUPANCY_SAMPLE);
+        for entry in self.buckets[..sample].iter().flat_map(|bucket| bucket.entries.iter()) {
+            if entry.is_used() {
+                let age = (ply as u8).wrapping_sub(entry.load().bound_age) & AGE_MASK;
+                histogram[std::cmp::min(age as usize, TT_AGE_HISTOGRAM - 1)] += 1;
             }
         }
-        hits = hits / TT_BUCKET_SIZE;
-        hits
+        histogram
+    }
+
+    // writes the table as a header followed by the raw words of every entry, all
+    // little endian. the header records the board size the table was filled with,
+    // along with the bucket layout, since entries are only found again in a table
+    // with the same number of buckets
+    pub fn write<W: Write>(&self, mut writer: W, board_size: usize) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&[board_size as u8, TT_BUCKET_SIZE as u8])?;
+        writer.write_all(&(self.size as u64).to_le_bytes())?;
+        for bucket in self.buckets.iter() {
+            for entry in bucket.entries.iter() {
+                writer.write_all(&entry.key.load(Ordering::Relaxed).to_le_bytes())?;
+                writer.write_all(&entry.data.load(Ordering::Relaxed).to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // reads a table written by write, which must be for the same board size.
+    // the table keeps the size it was saved with. the buckets are allocated as they
+    // are read, so a corrupt header cannot reserve more memory than the data covers
+    pub fn read<R: Read>(reader: R, board_size: usize) -> Result<Self> {
+        Self::read_checked(reader, board_size, None)
+    }
+
+    // reads a table, checking the size in the header against MAX_HASH_MB and, when
+    // it is known, the length of the file before anything is allocated
+    fn read_checked<R: Read>(mut reader: R, board_size: usize, file_len: Option<u64>) -> Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        ensure!(&header[0..4] == MAGIC, "Not a transposition table file");
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        ensure!(version == VERSION, "Unsupported transposition table version {}", version);
+        ensure!(
+            header[6] as usize == board_size,
+            "Transposition table is for size {}, not {}",
+            header[6],
+            board_size
+        );
+        ensure!(
+            header[7] as usize == TT_BUCKET_SIZE,
+            "Transposition table has {} entries per bucket, expected {}",
+            header[7],
+            TT_BUCKET_SIZE
+        );
+        let mut size = [0; 8];
+        size.copy_from_slice(&header[8..16]);
+        let size = u64::from_le_bytes(size);
+        ensure!(size > 0, "Transposition table is empty");
+        let max_size = (MAX_HASH_MB * MEGABYTE / std::mem::size_of::<HashBucket>()) as u64;
+        ensure!(
+            size <= max_size,
+            "Transposition table has {} buckets, more than fit in {} MB",
+            size,
+            MAX_HASH_MB
+        );
+        if let Some(len) = file_len {
+            let expected = (HEADER_LEN + TT_BUCKET_SIZE * ENTRY_LEN * size as usize) as u64;
+            ensure!(
+                len == expected,
+                "Transposition table file is {} bytes, expected {}",
+                len,
+                expected
+            );
+        }
+        let size = size as usize;
+        let mut buckets = Vec::with_capacity(size.min(READ_CHUNK));
+        let mut buf = [0; 8];
+        for _ in 0..size {
+            let bucket = HashBucket::new();
+            for entry in bucket.entries.iter() {
+                reader.read_exact(&mut buf)?;
+                entry.key.store(u64::from_le_bytes(buf), Ordering::Relaxed);
+                reader.read_exact(&mut buf)?;
+                entry.data.store(u64::from_le_bytes(buf), Ordering::Relaxed);
+            }
+            buckets.push(bucket);
+        }
+        Ok(Self { size, buckets })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, board_size: usize) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.write(std::io::BufWriter::new(file), board_size)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, board_size: usize) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read_checked(std::io::BufReader::new(file), board_size, Some(len))
     }
 
     #[inline]
-    pub fn put(&mut self, hash: u64, entry: HashEntry) {
+    pub fn put(&self, hash: u64, entry: HashEntry) -> Store {
         if TT_BUCKET_SIZE == 2 {
-            self.put_2way(hash, entry);
+            self.put_2way(hash, entry)
         } else {
-            self.put_any(hash, entry);
+            self.put_any(hash, entry)
         }
     }
 
     // put for 2 way hash table (bucket size == 2)
-    fn put_2way(&mut self, hash: u64, entry: HashEntry) {
-        let bucket = &mut self.buckets[hash as usize % self.size];
-        let depth_entry = bucket.entries[0];
-        if entry.depth() > depth_entry.depth() ||
-            entry.depth() + entry.ply > depth_entry.depth() + depth_entry.ply + TT_DISCARD_AGE
+    fn put_2way(&self, hash: u64, entry: HashEntry) -> Store {
+        let bucket = &self.buckets[hash as usize % self.size];
+        let depth_entry = bucket.entries[0].load();
+        let aged_depth = entry.depth() as usize + entry.age_since(&depth_entry) as usize;
+        let idx = if entry.depth() > depth_entry.depth() ||
+            aged_depth > depth_entry.depth() as usize + TT_DISCARD_AGE as usize
         {
-            bucket.entries[0] = entry;
+            0
         } else {
-            bucket.entries[1] = entry;
-        }
+            1
+        };
+        bucket.entries[idx].replace(hash, entry)
     }
 
     // put for arbitrary bucket size
-    fn put_any(&mut self, hash: u64, entry: HashEntry) {
+    fn put_any(&self, hash: u64, entry: HashEntry) -> Store {
         let slot: usize = hash as usize % self.size;
-        let bucket = &mut self.buckets[slot];
+        let bucket = &self.buckets[slot];
 
         let mut worst_idx = 0;
         let mut worst_score = 10000;
         for i in 0..TT_BUCKET_SIZE {
-            let cur_entry = bucket.entries[i];
+            let cur_entry = bucket.entries[i].load();
             // we never want 2 entries of same position!!!
             if cur_entry.check_hash(hash) {
                 if entry.depth() >= cur_entry.depth() {
-                    bucket.entries[i] = entry;
+                    bucket.entries[i].store(entry);
+                    return Store::Update;
                 }
-                return;
+                return Store::Kept;
             }
-            let score = ((cur_entry.depth() as usize) << TT_DEPTH_SHIFT) + (cur_entry.ply as usize);
+            let age = entry.age_since(&cur_entry);
+            let score = ((cur_entry.depth() as usize) << TT_DEPTH_SHIFT) + (AGE_MASK - age) as usize;
             if score < worst_score {
                 worst_score = score;
                 worst_idx = i;
             }
         }
-        bucket.entries[worst_idx] = entry;
+        bucket.entries[worst_idx].replace(hash, entry)
+    }
+
+    #[inline]
+    pub fn get(&self, hash: &u64) -> Option<HashEntry> {
+        match self.probe(*hash) {
+            Probe::Hit(entry) => Some(entry),
+            _ => None,
+        }
     }
 
     #[inline]
-    pub fn get(&self, hash: &u64) -> Option<&HashEntry> {
-        let slot: usize = *hash as usize % self.size;
+    pub fn probe(&self, hash: u64) -> Probe {
+        let slot: usize = hash as usize % self.size;
+        let mut full = true;
         for i in 0..TT_BUCKET_SIZE {
-            if self.buckets[slot].entries[i].check_hash(*hash) {
-                return Some(&self.buckets[slot].entries[i]);
+            let atomic = &self.buckets[slot].entries[i];
+            let entry = atomic.load();
+            if entry.check_hash(hash) {
+                return Probe::Hit(entry);
             }
+            full &= atomic.is_used();
+        }
+        if full {
+            Probe::Collision
+        } else {
+            Probe::Miss
         }
-        None
     }
 }
 
-#[derive(Clone, Copy)]
-#[repr(align(32))]
+#[repr(align(64))]
 struct HashBucket {
-    entries: [HashEntry; TT_BUCKET_SIZE],
+    entries: [AtomicEntry; TT_BUCKET_SIZE],
 }
 
 impl HashBucket {
     fn new() -> Self {
         Self {
-            entries: [HashEntry::empty(); TT_BUCKET_SIZE],
+            entries: Default::default(),
         }
     }
 }
 
-// right now this totals to 16 bytes, extended to 24 bytes for 8 byte memory alignment.
-// it would be nice to get this into 16 bytes!
-// this would be not only mean less memory usage, but also be much faster because of
-// cache utilization. each slot (2 entries) would be loaded with a single cache line.
+// the packed form of a HashEntry, 16 bytes, written and read without locking.
+// key is hash ^ data, so a torn entry fails the hash check
+#[derive(Default)]
+struct AtomicEntry {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl AtomicEntry {
+    fn load(&self) -> HashEntry {
+        let key = self.key.load(Ordering::Relaxed);
+        let data = self.data.load(Ordering::Relaxed);
+        HashEntry::unpack(key ^ data, data)
+    }
+
+    fn store(&self, entry: HashEntry) {
+        let data = entry.pack();
+        self.key.store(entry.hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    fn clear(&self) {
+        self.key.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+
+    fn is_used(&self) -> bool {
+        (self.key.load(Ordering::Relaxed) | self.data.load(Ordering::Relaxed)) != 0
+    }
+
+    // stores an entry in place of whatever was here before
+    fn replace(&self, hash: u64, entry: HashEntry) -> Store {
+        let outcome = if !self.is_used() {
+            Store::Empty
+        } else if self.load().check_hash(hash) {
+            Store::Update
+        } else {
+            Store::Overwrite
+        };
+        self.store(entry);
+        outcome
+    }
+}
+
+// outcome of looking up a position
+pub enum Probe {
+    Hit(HashEntry),
+    Miss,
+    // a miss where every entry of the bucket holds another position
+    Collision,
+}
+
+// what happened when an entry was put in the table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Store {
+    // went into an unused slot
+    Empty,
+    // replaced an older entry of the same position
+    Update,
+    // the same position was already stored with a greater depth, so nothing changed
+    Kept,
+    // evicted the entry of another position
+    Overwrite,
+}
+
+// unpacked entry as handed to and from the search.
+// in the table everything but the hash is packed into a single word:
+//
+// bits  0..16  score, saturated to i16. real scores are far inside this range, so
+//              only the +-1_000_000 window bounds are affected and they stay valid
+// bits 16..24  depth
+// bits 24..32  bound flags in the top 2 bits, age in the low 6
+// bits 32..56  move, see GameMove::compact
+//
+// the age is the ply of the position modulo 64, so it is only meaningful relative
+// to the ply of another entry, see age_since. the legacy-tt-layout feature keeps the
+// previous layout, with depth and flags sharing a byte and the full 28 bit move,
+// so the two can be compared in the benchmarks
 #[derive(Clone, Copy)]
 pub struct HashEntry {
-    hash_hi: u16,            // 2 bytes (~3 low bytes are encoded in the HashTable idx)
-    pub game_move: GameMove, // 8 bytes
-    score_val: i32,          // 4 bytes
-    depth_flags: u8,         // 1 byte
-    ply: u8,                 // 1 byte
+    hash: u64,               // only its low bytes are encoded in the HashTable idx
+    pub game_move: GameMove,
+    score_val: i16,
+    depth: u8,
+    bound_age: u8,
 }
 
 const ALPHA_FLAG: u8 = 0b10000000;
 const BETA_FLAG:  u8 = 0b01000000;
-const DEPTH_MASK: u8 = 0b00111111;
+const BOUND_MASK: u8 = 0b11000000;
+const AGE_MASK:   u8 = 0b00111111;
+const DEPTH_MASK: u8 = 0b11111111;
+const LEGACY_DEPTH_MASK: u8 = 0b00111111;
 
 impl HashEntry {
-    fn empty() -> Self {
-        Self::new(1, GameMove::null_move(), ScoreCutoff::Alpha(0), 0, 0)
-    }
-
     pub fn new(hash: u64, game_move: GameMove, score: ScoreCutoff, depth: usize, ply: usize) -> Self {
-        let depth_flags: u8;
+        debug_assert!(depth <= DEPTH_MASK as usize);
+        let flags: u8;
         let score_val: i32;
         match score {
             ScoreCutoff::Alpha(s) => {
                 score_val = s;
-                depth_flags = ALPHA_FLAG | (depth as u8);
+                flags = ALPHA_FLAG;
             }
             ScoreCutoff::Beta(s) => {
                 score_val = s;
-                depth_flags = BETA_FLAG | (depth as u8);
+                flags = BETA_FLAG;
             }
             ScoreCutoff::Exact(s) => {
                 score_val = s;
-                depth_flags = depth as u8;
+                flags = 0;
             }
         }
         Self {
-            hash_hi: (hash >> 48) as u16,
+            hash,
             game_move,
-            score_val,
-            depth_flags,
-            ply: ply as u8,
+            score_val: score_val.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+            depth: depth as u8,
+            bound_age: flags | (ply as u8 & AGE_MASK),
         }
     }
 
     pub fn depth(&self) -> u8 {
-        self.depth_flags & DEPTH_MASK
+        self.depth
+    }
+
+    // how many plies before this entry the other one was stored, modulo 64.
+    // entries left over from later plies of another game count as old
+    fn age_since(&self, other: &Self) -> u8 {
+        self.bound_age.wrapping_sub(other.bound_age) & AGE_MASK
+    }
+
+    /// Replaces the score, keeping the kind of bound
+    pub(crate) fn map_score<F: FnOnce(i32) -> i32>(mut self, f: F) -> Self {
+        let score = f(self.score_val as i32);
+        self.score_val = score.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        self
     }
 
     pub fn score(&self) -> ScoreCutoff {
-        if (self.depth_flags & BETA_FLAG) != 0 {
-            return ScoreCutoff::Beta(self.score_val);
+        if (self.bound_age & BETA_FLAG) != 0 {
+            return ScoreCutoff::Beta(self.score_val as i32);
         }
-        if (self.depth_flags & ALPHA_FLAG) != 0 {
-            return ScoreCutoff::Alpha(self.score_val);
+        if (self.bound_age & ALPHA_FLAG) != 0 {
+            return ScoreCutoff::Alpha(self.score_val as i32);
         }
-        return ScoreCutoff::Exact(self.score_val);
+        ScoreCutoff::Exact(self.score_val as i32)
     }
 
     #[inline]
     pub fn check_hash(&self, hash: u64) -> bool {
-        self.hash_hi == (hash >> 48) as u16
+        self.hash == hash
+    }
+
+    fn pack(&self) -> u64 {
+        if cfg!(feature = "legacy-tt-layout") {
+            let depth_flags = (self.bound_age & BOUND_MASK) | (self.depth & LEGACY_DEPTH_MASK);
+            return (self.score_val as u16 as u64)
+                | ((depth_flags as u64) << 16)
+                | (((self.bound_age & AGE_MASK) as u64) << 24)
+                | ((self.game_move.raw() as u64) << 32);
+        }
+        (self.score_val as u16 as u64)
+            | ((self.depth as u64) << 16)
+            | ((self.bound_age as u64) << 24)
+            | ((self.game_move.compact() as u64) << 32)
+    }
+
+    fn unpack(hash: u64, data: u64) -> Self {
+        if cfg!(feature = "legacy-tt-layout") {
+            let depth_flags = (data >> 16) as u8;
+            return Self {
+                hash,
+                game_move: GameMove::from_raw((data >> 32) as u32),
+                score_val: data as u16 as i16,
+                depth: depth_flags & LEGACY_DEPTH_MASK,
+                bound_age: (depth_flags & BOUND_MASK) | (data >> 24) as u8,
+            };
+        }
+        Self {
+            hash,
+            game_move: GameMove::from_compact((data >> 32) as u32),
+            score_val: data as u16 as i16,
+            depth: (data >> 16) as u8,
+            bound_age: (data >> 24) as u8,
+        }
     }
 }
 
@@ -197,3 +492,152 @@ pub enum ScoreCutoff {
     Beta(i32),
     Exact(i32),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn sized_in_megabytes() {
+        let mut table = HashTable::with_mb(4);
+        assert_eq!(table.size_mb(), 4);
+        assert_eq!(table.size, 4 * MEGABYTE / std::mem::size_of::<HashBucket>());
+        let hash = 12345;
+        table.put(hash, HashEntry::new(hash, GameMove::null_move(), ScoreCutoff::Exact(1), 2, 2));
+        assert!(table.get(&hash).is_some());
+        table.resize(1);
+        assert_eq!(table.size_mb(), 1);
+        assert!(table.get(&hash).is_none());
+        assert_eq!(HashTable::with_mb(0).size, 1);
+    }
+
+    #[test]
+    fn packed_entries() {
+        assert_eq!(std::mem::size_of::<AtomicEntry>(), 16);
+        assert_eq!(std::mem::size_of::<HashBucket>(), 64);
+        let m = GameMove::from_raw(0x1FFFFF).set_crush();
+        let hash = 0xDEAD_BEEF_1234_5678;
+        let entry = HashEntry::new(hash, m, ScoreCutoff::Alpha(-9_876), 63, 255);
+        let unpacked = HashEntry::unpack(hash, entry.pack());
+        assert_eq!(unpacked.game_move, m);
+        assert_eq!(unpacked.depth(), 63);
+        assert_eq!(unpacked.bound_age & AGE_MASK, 63);
+        assert!(matches!(unpacked.score(), ScoreCutoff::Alpha(-9_876)));
+        // Depth has a byte of its own
+        if !cfg!(feature = "legacy-tt-layout") {
+            let deep = HashEntry::new(hash, m, ScoreCutoff::Beta(5), MAX_TT_DEPTH, 3);
+            let unpacked = HashEntry::unpack(hash, deep.pack());
+            assert_eq!(unpacked.depth() as usize, MAX_TT_DEPTH);
+            assert!(matches!(unpacked.score(), ScoreCutoff::Beta(5)));
+        }
+        // Ages are relative and wrap around
+        let older = HashEntry::new(hash, m, ScoreCutoff::Exact(0), 1, 250);
+        assert_eq!(unpacked.age_since(&older), 5);
+        assert_eq!(older.age_since(&unpacked), 59);
+        // Window bounds saturate instead of wrapping around
+        let entry = HashEntry::new(hash, m, ScoreCutoff::Beta(1_000_000), 1, 1);
+        assert!(matches!(entry.score(), ScoreCutoff::Beta(32767)));
+        let entry = HashEntry::new(hash, m, ScoreCutoff::Alpha(-1_000_000), 1, 1);
+        assert!(matches!(entry.score(), ScoreCutoff::Alpha(-32768)));
+    }
+
+    #[test]
+    fn occupancy_and_stores() {
+        let table = HashTable::new(10 * TT_BUCKET_SIZE);
+        assert_eq!(table.occupancy(), 0);
+        let entry = |hash, depth| HashEntry::new(hash, GameMove::null_move(), ScoreCutoff::Exact(0), depth, 12);
+        // Every hash is a multiple of the bucket count, so they share a bucket
+        for i in 1..=TT_BUCKET_SIZE as u64 {
+            assert_eq!(table.put(i * 10, entry(i * 10, 3)), Store::Empty);
+        }
+        assert_eq!(table.occupancy(), 100);
+        assert_eq!(table.put(10, entry(10, 2)), Store::Kept);
+        assert_eq!(table.put(10, entry(10, 4)), Store::Update);
+        assert!(matches!(table.probe(1000), Probe::Collision));
+        assert!(matches!(table.probe(1001), Probe::Miss));
+        assert_eq!(table.put(1000, entry(1000, 5)), Store::Overwrite);
+        let histogram = table.age_histogram(14);
+        assert_eq!(histogram[2], TT_BUCKET_SIZE);
+        assert_eq!(histogram.iter().sum::<usize>(), TT_BUCKET_SIZE);
+        for i in 0..10 * TT_BUCKET_SIZE as u64 {
+            table.put(i, entry(i, 3));
+        }
+        assert_eq!(table.occupancy(), 1000);
+    }
+
+    #[test]
+    fn read_write() {
+        let table = HashTable::new(100 * TT_BUCKET_SIZE);
+        let hash = 0x1234_5678_9ABC_DEF0;
+        let m = GameMove::from_raw(0x2000010);
+        table.put(hash, HashEntry::new(hash, m, ScoreCutoff::Beta(-50), 7, 20));
+        let mut buf = Vec::new();
+        table.write(&mut buf, 6).unwrap();
+        assert!(HashTable::read(&buf[..], 5).is_err());
+        let read = HashTable::read(&buf[..], 6).unwrap();
+        assert_eq!(read.size, table.size);
+        let entry = read.get(&hash).unwrap();
+        assert_eq!(entry.game_move, m);
+        assert_eq!(entry.depth(), 7);
+        assert!(matches!(entry.score(), ScoreCutoff::Beta(-50)));
+        assert!(HashTable::read(&buf[..buf.len() - 1], 6).is_err());
+        assert!(HashTable::read_checked(&buf[..], 6, Some(buf.len() as u64)).is_ok());
+        assert!(HashTable::read_checked(&buf[..], 6, Some(buf.len() as u64 - 1)).is_err());
+        // A corrupt size fails before anything is allocated
+        let mut huge = buf.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(HashTable::read(&huge[..], 6).is_err());
+        // The largest valid size without the data behind it fails after one chunk
+        let max_size = MAX_HASH_MB * MEGABYTE / std::mem::size_of::<HashBucket>();
+        huge[8..16].copy_from_slice(&(max_size as u64).to_le_bytes());
+        assert!(HashTable::read(&huge[..], 6).is_err());
+        buf[4] = 99;
+        assert!(HashTable::read(&buf[..], 6).is_err());
+    }
+
+    #[test]
+    fn concurrent_access() {
+        // Every thread writes entries whose contents are derived from the hash, with
+        // all hashes landing in the same few buckets to force overwrites
+        let table = Arc::new(HashTable::new(4 * TT_BUCKET_SIZE));
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let table = table.clone();
+                std::thread::spawn(move || {
+                    for i in 0..20_000u64 {
+                        let hash = ((i * 4 + t) << 8) | (i % 4);
+                        let score = ((hash >> 8) % 30_000) as i32;
+                        let entry = HashEntry::new(
+                            hash,
+                            GameMove::from_raw(score as u32),
+                            ScoreCutoff::Exact(score),
+                            (i % 32) as usize,
+                            (i % 200) as usize,
+                        );
+                        table.put(hash, entry);
+                        let probe = ((i * 4 + (t + 1) % 4) << 8) | (i % 4);
+                        if let Some(found) = table.get(&probe) {
+                            assert_eq!(found.game_move.raw(), ((probe >> 8) % 30_000) as u32);
+                            match found.score() {
+                                ScoreCutoff::Exact(s) => assert_eq!(s, ((probe >> 8) % 30_000) as i32),
+                                _ => panic!("Wrong score type"),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let hash = 5 << 8;
+        table.put(
+            hash,
+            HashEntry::new(hash, GameMove::null_move(), ScoreCutoff::Beta(7), 3, 10),
+        );
+        assert_eq!(table.get(&hash).map(|e| e.depth()), Some(3));
+        table.clear();
+        assert!(table.get(&hash).is_none());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::board::TakBoard;
+use crate::eval::{Evaluator, Weights};
+use crate::{Color, GameResult};
+use anyhow::{anyhow, ensure, Result};
+use std::path::Path;
+
+/// A position from a finished game along with the final result from white's
+/// perspective: 1.0 for a white win, 0.5 for a draw and 0.0 for a black win.
+pub struct TunePosition<T> {
+    board: T,
+    result: f64,
+}
+
+impl<T: TakBoard> TunePosition<T> {
+    pub fn new(board: T, result: GameResult) -> Self {
+        let result = match result {
+            GameResult::WhiteWin => 1.0,
+            GameResult::BlackWin => 0.0,
+            GameResult::Draw => 0.5,
+        };
+        Self { board, result }
+    }
+    /// Parses a dataset line of the form `<tps>;<result>[;<anything else>]`. The result
+    /// may be written as in ptn (`1-0`, `R-0`, `0-F`, `1/2-1/2`) or as a number.
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut fields = line.split(';');
+        let tps = fields.next().unwrap().trim();
+        let result = fields
+            .next()
+            .ok_or_else(|| anyhow!("Missing result in line: {}", line))?
+            .trim();
+        let board = T::try_from_tps(tps)?;
+        let result = parse_result(result)?;
+        Ok(Self { board, result })
+    }
+}
+
+fn parse_result(s: &str) -> Result<f64> {
+    let res = match s {
+        "1-0" | "R-0" | "F-0" => 1.0,
+        "0-1" | "0-R" | "0-F" => 0.0,
+        "1/2-1/2" | "1/2" => 0.5,
+        _ => s
+            .parse()
+            .map_err(|_| anyhow!("Unable to parse game result {}", s))?,
+    };
+    ensure!((0.0..=1.0).contains(&res), "Game result out of range: {}", s);
+    Ok(res)
+}
+
+/// Reads a dataset of positions, one per line. Positions where the side to move
+/// can make a road in one move are skipped, as the static evaluation does not see
+/// the win and they would only add noise to the fit.
+pub fn read_dataset<T: TakBoard, P: AsRef<Path>>(path: P) -> Result<Vec<TunePosition<T>>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut positions = Vec::new();
+    let mut storage = Vec::new();
+    for (num, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let pos: TunePosition<T> =
+            TunePosition::parse(line).map_err(|e| anyhow!("Line {}: {}", num + 1, e))?;
+        storage.clear();
+        if pos.board.can_make_road(&mut storage, None).is_none() {
+            positions.push(pos);
+        }
+    }
+    Ok(positions)
+}
+
+/// Texel style tuner for [`Weights`].
+///
+/// The static evaluation of every position is mapped to an expected game result
+/// with a sigmoid, and each weight is nudged up and down in turn, keeping any change
+/// that lowers the mean squared error against the actual results. Positions are
+/// evaluated at the parity of their ply so that the tempo offset takes part in the fit.
+pub struct Tuner<T> {
+    positions: Vec<TunePosition<T>>,
+    k: f64,
+}
+
+impl<T: TakBoard> Tuner<T> {
+    pub fn new(positions: Vec<TunePosition<T>>) -> Self {
+        Self { positions, k: 1.0 }
+    }
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+    /// Mean squared error of the predicted results for the given weights
+    pub fn error(&self, weights: &Weights<T>) -> f64 {
+        let total: f64 = self
+            .positions
+            .iter()
+            .map(|pos| {
+                let mut score = weights.evaluate(&pos.board, pos.board.ply() % 2) as f64;
+                if let Color::Black = pos.board.active_player() {
+                    score = -score;
+                }
+                let diff = pos.result - sigmoid(self.k, score);
+                diff * diff
+            })
+            .sum();
+        total / self.positions.len() as f64
+    }
+    /// Finds the sigmoid scaling constant which best fits the current weights, so
+    /// that the weights are not simply scaled up or down during tuning
+    pub fn fit_k(&mut self, weights: &Weights<T>) -> f64 {
+        let mut best = (self.k, self.error(weights));
+        let mut step = 0.5;
+        for _ in 0..20 {
+            for &k in [best.0 - step, best.0 + step].iter() {
+                if k <= 0.0 {
+                    continue;
+                }
+                self.k = k;
+                let err = self.error(weights);
+                if err < best.1 {
+                    best = (k, err);
+                }
+            }
+            step /= 2.0;
+        }
+        self.k = best.0;
+        self.k
+    }
+    /// Runs at most `iterations` passes over every weight, stopping early once no
+    /// single step improves the error. Returns the tuned weights and final error.
+    /// Uses the scaling constant from the last call to [`Tuner::fit_k`], which should
+    /// be fitted to the initial weights first.
+    pub fn tune(&self, weights: &Weights<T>, iterations: usize) -> (Weights<T>, f64) {
+        let mut params = weights.params();
+        let mut best_err = self.error(weights);
+        for _ in 0..iterations {
+            let mut improved = false;
+            for idx in 0..params.len() {
+                for &delta in [1, -1].iter() {
+                    params[idx] += delta;
+                    let err = self.error(&Weights::from_params(&params));
+                    if err < best_err {
+                        best_err = err;
+                        improved = true;
+                        break;
+                    }
+                    params[idx] -= delta;
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+        (Weights::from_params(&params), best_err)
+    }
+}
+
+/// Expected result for white given a centipawn style score
+fn sigmoid(k: f64, score: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::Board5;
+    use crate::eval::Weights5;
+    #[test]
+    fn parse_positions() {
+        let pos: TunePosition<Board5> =
+            TunePosition::parse("x5/x5/x5/x5/x5 1 1;1/2-1/2;0").unwrap();
+        assert_eq!(pos.result, 0.5);
+        assert!(TunePosition::<Board5>::parse("x5/x5/x5/x5/x5 1 1").is_err());
+        assert!(TunePosition::<Board5>::parse("x5/x5/x5/x5/x5 1 1;2").is_err());
+        assert_eq!(parse_result("0-R").unwrap(), 0.0);
+    }
+    #[test]
+    fn tuning_reduces_error() {
+        let lines = [
+            "x5/x,1,1,x2/x,1,2,x2/x,1,2C,x2/2,x4 2 5;1-0",
+            "x5/x,2,2,x2/x,2,1,x2/x,2,1C,x2/1,x4 1 5;0-1",
+            "x5/x,1,1,1,x/x,2,2,x2/x2,2C,x2/x5 2 5;1-0",
+            "x5/x,2,2,2,x/x,1,1,x2/x2,1C,x2/x5 1 5;0-1",
+        ];
+        let positions = lines.iter().map(|l| TunePosition::parse(l).unwrap()).collect();
+        let mut tuner = Tuner::new(positions);
+        let weights = Weights5::default();
+        tuner.fit_k(&weights);
+        let before = tuner.error(&weights);
+        let (tuned, after) = tuner.tune(&weights, 3);
+        assert!(after <= before);
+        assert_eq!(after, tuner.error(&tuned));
+    }
+}